@@ -0,0 +1,176 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    Data, DeriveInput, Error, Expr, ExprLit, Fields, Ident, Lit, Meta, MetaNameValue, Token,
+    parse_macro_input, punctuated::Punctuated,
+};
+
+///
+/// Implements `EffectParams` and `Default` for a struct of effect parameters
+///
+/// Every field has a doc comment, used as tooltip, and a `#[param]` attribute giving its
+/// default and optionally its `min`, `max` and `step`. Fields with both bounds get a slider,
+/// other numbers a drag value, `bool` a checkbox and `ParamChoice` enums a combo box.
+///
+/// ```ignore
+/// #[derive(Clone, Debug, EffectParams)]
+/// pub struct BlurParams {
+///     /// Radius of the kernel in pixels
+///     #[param(default = 4.0, min = 0.0, max = 32.0)]
+///     pub radius: f32,
+///     /// Blurs along both axes
+///     #[param(default = true)]
+///     pub two_pass: bool,
+/// }
+/// ```
+///
+#[proc_macro_derive(EffectParams, attributes(param))]
+pub fn derive_effect_params(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+struct Param {
+    ident: Ident,
+    /// Lines of the doc comment
+    tooltip: String,
+    default: Expr,
+    /// `min`, `max` and `step`, in the order given
+    bounds: Vec<(Ident, Expr)>,
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(
+            input,
+            "EffectParams can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(
+            &data.fields,
+            "EffectParams needs named fields",
+        ));
+    };
+    let params = fields
+        .named
+        .iter()
+        .map(parse_param)
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let idents: Vec<&Ident> = params.iter().map(|param| &param.ident).collect();
+    let indices = 0..params.len();
+    let defaults = params.iter().map(|param| &param.default);
+    let infos = params.iter().map(|param| {
+        let name = param.ident.to_string();
+        let tooltip = &param.tooltip;
+        let bounds = param
+            .bounds
+            .iter()
+            .map(|(key, value)| quote!(#key: Some((#value) as f64),));
+        quote! {
+            ::seagull_lib::effect_params::ParamInfo {
+                name: #name,
+                tooltip: #tooltip,
+                #(#bounds)*
+                ..::seagull_lib::effect_params::ParamInfo::DEFAULT
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::std::default::Default for #name #type_generics #where_clause {
+            fn default() -> Self {
+                Self {
+                    #(#idents: #defaults,)*
+                }
+            }
+        }
+
+        impl #impl_generics ::seagull_lib::effect_params::EffectParams
+            for #name #type_generics #where_clause
+        {
+            const PARAMS: &'static [::seagull_lib::effect_params::ParamInfo] = &[#(#infos),*];
+
+            fn edit(&mut self, ui: &mut ::egui::Ui) -> bool {
+                let mut changed = false;
+                #(
+                    let info = &Self::PARAMS[#indices];
+                    ui.label(info.label()).on_hover_text(info.tooltip.trim());
+                    changed |=
+                        ::seagull_lib::effect_params::Param::ui(&mut self.#idents, ui, info);
+                    ui.end_row();
+                )*
+                changed
+            }
+        }
+    })
+}
+
+fn parse_param(field: &syn::Field) -> syn::Result<Param> {
+    let mut lines = Vec::new();
+    let mut args = None;
+    for attr in &field.attrs {
+        if attr.path().is_ident("doc") {
+            if let Meta::NameValue(MetaNameValue {
+                value:
+                    Expr::Lit(ExprLit {
+                        lit: Lit::Str(line),
+                        ..
+                    }),
+                ..
+            }) = &attr.meta
+            {
+                lines.push(line.value().trim().to_owned());
+            }
+        } else if attr.path().is_ident("param") {
+            if args.is_some() {
+                return Err(Error::new_spanned(attr, "Duplicate #[param] attribute"));
+            }
+            args = Some(
+                attr.parse_args_with(Punctuated::<MetaNameValue, Token![,]>::parse_terminated)?,
+            );
+        }
+    }
+
+    let Some(args) = args else {
+        return Err(Error::new_spanned(
+            field,
+            "Missing #[param(default = ..)] attribute",
+        ));
+    };
+
+    let mut default = None;
+    let mut bounds = Vec::new();
+    for arg in args {
+        let Some(key) = arg.path.get_ident() else {
+            return Err(Error::new_spanned(&arg.path, "Expected a parameter key"));
+        };
+        match key.to_string().as_str() {
+            "default" => default = Some(arg.value),
+            "min" | "max" | "step" => bounds.push((key.clone(), arg.value)),
+            _ => {
+                return Err(Error::new_spanned(
+                    key,
+                    "Unknown parameter key, expected default, min, max or step",
+                ));
+            }
+        }
+    }
+
+    let Some(default) = default else {
+        return Err(Error::new_spanned(field, "Missing default in #[param]"));
+    };
+
+    Ok(Param {
+        ident: field.ident.clone().unwrap(),
+        tooltip: lines.join("\n"),
+        default,
+        bounds,
+    })
+}
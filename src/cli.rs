@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use winit::dpi::PhysicalSize;
+
+use crate::{effect::EffectKind, recorder::RecordFormat};
+
+///
+/// Interactive and offline renderer of the seagull effects
+///
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub options: Options,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Args)]
+pub struct Options {
+    /// Effects to chain, in order, like `voronoi,pixelate`
+    #[arg(short, long = "effect", value_name = "EFFECT", value_delimiter = ',')]
+    pub effects: Vec<EffectKind>,
+    /// Inner size of the window, like `1280x720`
+    #[arg(long, value_parser = parse_size)]
+    pub size: Option<PhysicalSize<u32>>,
+    /// Opens the window borderless fullscreen
+    #[arg(long)]
+    pub fullscreen: bool,
+    /// Waits for the vertical blank before presenting, the driver decides when omitted
+    #[arg(long)]
+    pub vsync: Option<bool>,
+    /// Seed of the random sites
+    #[arg(long, conflicts_with = "preset")]
+    pub seed: Option<u64>,
+    /// Palette of the pixelate effect, one hex color per line
+    #[arg(long)]
+    pub palette: Option<PathBuf>,
+    /// Preset to start from, the other options override it
+    #[arg(long)]
+    pub preset: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Records frames offscreen at a fixed time step
+    Render(RenderArgs),
+    /// Prints the effects and their parameters
+    List,
+}
+
+#[derive(Debug, Args)]
+pub struct RenderArgs {
+    /// File format of the recording
+    #[arg(short, long, value_enum, default_value_t = RecordFormat::Png)]
+    pub format: RecordFormat,
+    /// Number of frames to render
+    #[arg(short = 'n', long, default_value_t = 60)]
+    pub frames: u32,
+    /// Size of the frames, like `1920x1080`
+    #[arg(long, default_value = "1920x1080", value_parser = parse_size)]
+    pub resolution: PhysicalSize<u32>,
+    /// Simulated seconds between two frames
+    #[arg(long, default_value_t = 1.0 / 60.0)]
+    pub time_step: f32,
+    /// Directory of the PNG files or file of the video, `frames` or `recording.<format>`
+    /// when omitted
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+///
+/// Parses `WIDTHxHEIGHT`, both being at least 1
+///
+fn parse_size(value: &str) -> Result<PhysicalSize<u32>, String> {
+    let invalid = || format!("Invalid size '{value}', expected WIDTHxHEIGHT");
+    let (width, height) = value.split_once('x').ok_or_else(invalid)?;
+    let width: u32 = width.parse().map_err(|_| invalid())?;
+    let height: u32 = height.parse().map_err(|_| invalid())?;
+    if width == 0 || height == 0 {
+        return Err(invalid());
+    }
+    Ok(PhysicalSize::new(width, height))
+}
+
+///
+/// Prints every effect followed by its parameters, their range and description
+///
+pub fn list_effects() {
+    for effect in EffectKind::ALL {
+        println!("{}", effect.name());
+        for param in effect.params() {
+            let range = match (param.min, param.max) {
+                (Some(min), Some(max)) => format!("{min} to {max}"),
+                (Some(min), None) => format!("from {min}"),
+                (None, Some(max)) => format!("up to {max}"),
+                (None, None) => String::new(),
+            };
+            println!("  {:<16}{range:<16}{}", param.name, param.tooltip.trim());
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use seagull_lib::effect_params::{EffectParams, ParamInfo};
+use serde::{Deserialize, Serialize};
+
+use crate::{pixelate::PixelateParams, voronoi::VoronoiParams};
+
+///
+/// Effects that can be chained, each one drawing over or from the image of the previous one
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum EffectKind {
+    /// Diagram of the sites, ignores its input
+    Voronoi,
+    /// Quantizes its input to the palette, or a swirl when first
+    Pixelate,
+}
+
+impl EffectKind {
+    /// Every effect, in the order they are chained
+    pub const ALL: [EffectKind; 2] = [EffectKind::Voronoi, EffectKind::Pixelate];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EffectKind::Voronoi => "voronoi",
+            EffectKind::Pixelate => "pixelate",
+        }
+    }
+
+    ///
+    /// Whether the effect draws from the image of the previous one
+    ///
+    pub fn uses_input(self) -> bool {
+        match self {
+            EffectKind::Voronoi => false,
+            EffectKind::Pixelate => true,
+        }
+    }
+
+    pub fn params(self) -> &'static [ParamInfo] {
+        match self {
+            EffectKind::Voronoi => VoronoiParams::PARAMS,
+            EffectKind::Pixelate => PixelateParams::PARAMS,
+        }
+    }
+}
+
+/// Chain shown when nothing else is asked for
+pub const DEFAULT_CHAIN: [EffectKind; 1] = [EffectKind::Voronoi];
+
+///
+/// Checks that a chain draws something and that no effect discards the image of the
+/// previous one
+///
+pub fn validate_chain(chain: &[EffectKind]) -> Result<()> {
+    if chain.is_empty() {
+        return Err(anyhow::anyhow!("The effect chain is empty"));
+    }
+    for (index, effect) in chain.iter().enumerate() {
+        if chain[..index].contains(effect) {
+            return Err(anyhow::anyhow!(
+                "The effect chain has {} twice",
+                effect.name()
+            ));
+        }
+        if index > 0 && !effect.uses_input() {
+            return Err(anyhow::anyhow!(
+                "{} ignores its input, it can only start the chain",
+                effect.name()
+            ));
+        }
+    }
+    Ok(())
+}
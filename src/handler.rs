@@ -1,63 +1,483 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Mutex,
+};
 
 use anyhow::Result;
-use egui::{CentralPanel, Id, SidePanel};
+use egui::{Button, CentralPanel, Checkbox, Id, SidePanel};
 use egui_glow::Painter;
 use glm::{Vec2, vec2};
-use glow::{HasContext, SCISSOR_TEST};
-use log::{error, info};
-use rand::Rng;
-use seagull_lib::app::{AppContext, HandleApp};
+use glow::{HasContext, RGBA8, SCISSOR_TEST, TEXTURE0};
+use log::{error, warn};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use seagull_lib::{
+    app::{AppContext, HandleApp, InitContext},
+    clock::ClockMode,
+    effect_params::EffectParams,
+    frame_buffer::{AttachmentDesc, FrameBufferDesc},
+    fullscreen_pass::{FullscreenPass, Viewport},
+    gl_state::GlState,
+    render_graph::{RenderGraph, ResourceId},
+    shader::Shader,
+};
 use winit::{dpi::PhysicalSize, event::WindowEvent, window::Window};
 
-use crate::voronoi::{self, Voronoi};
+use crate::{
+    effect::{DEFAULT_CHAIN, EffectKind},
+    pixelate::{Pixelate, PixelateParams, palette::Palette},
+    preset::{
+        PRESET_VERSION, PixelatePreset, Preset, RECENT_PRESETS_PATH, RecentPresets, VoronoiPreset,
+    },
+    recorder::{Recorder, RecorderConfig},
+    voronoi::{self, Voronoi, VoronoiParams, editor::SiteEditor},
+};
 
 pub struct AppHandler {
+    chain: Vec<EffectKind>,
     voronoi: Voronoi,
+    pixelate: Pixelate,
+    site_editor: SiteEditor,
+    seed: u64,
+    rng: StdRng,
+    preset_path: String,
+    recent_presets: RecentPresets,
+    recorder: Option<Recorder>,
+    /// Settings of the next recording started from the UI
+    recorder_config: RecorderConfig,
+    /// Mode and time scale the clock goes back to once the recording started from the UI stops
+    clock_before_recording: Option<(ClockMode, f32)>,
+    /// Set by the `render` subcommand, the UI is skipped and the app exits after recording
+    headless: bool,
+    window_size: PhysicalSize<u32>,
+    fullscreen_pass: Rc<FullscreenPass>,
+    /// Shows the frames being recorded in the window
+    display_shader: Shader,
     egui_state: egui_winit::State,
     egui_painter: Painter,
 }
 
-fn generate_random_vec2s() -> Vec<Vec2> {
-    let mut rng = rand::rng();
-    (0..16)
-        .map(|_| vec2(rng.random_range(-1.0..1.0), rng.random_range(-1.0..1.0)))
+///
+/// Startup configuration for the demo handler
+///
+#[derive(Default)]
+pub struct HandlerConfig {
+    pub seed: Option<u64>,
+    /// Preset applied at startup, its seed replaces `seed`
+    pub preset: Option<PathBuf>,
+    /// Replaces the chain of the preset when not empty
+    pub chain: Vec<EffectKind>,
+    /// Replaces the palette of the preset
+    pub palette: Option<PathBuf>,
+    /// Recording started right away, without UI
+    pub render: Option<RecorderConfig>,
+}
+
+///
+/// Largest part of `target` with the aspect ratio of `source`, centered
+///
+fn letterbox(source: Viewport, target: Viewport) -> Viewport {
+    let scale = (target.width as f32 / source.width as f32)
+        .min(target.height as f32 / source.height as f32);
+    let width = (source.width as f32 * scale) as i32;
+    let height = (source.height as f32 * scale) as i32;
+    Viewport::new(
+        target.x + (target.width - width) / 2,
+        target.y + (target.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+fn generate_random_vec2s(rng: &mut StdRng, count: usize) -> Vec<Vec2> {
+    (0..count)
+        .map(|_| vec2(rng.random_range(0.0..1.0), rng.random_range(0.0..1.0)))
         .collect()
 }
 
 impl AppHandler {
-    pub fn new(gl: Arc<glow::Context>, window: &Window, size: &PhysicalSize<u32>) -> Result<Self> {
-        let voronoi = Voronoi::new(gl.clone(), generate_random_vec2s())?;
+    pub fn new(context: &mut InitContext, config: HandlerConfig) -> Result<Self> {
+        let gl = context.gl.context().clone();
+        // Kept so that presets can record it
+        let seed = config.seed.unwrap_or_else(rand::random);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let voronoi = Voronoi::new(
+            context.gl.clone(),
+            context.fullscreen_pass.clone(),
+            generate_random_vec2s(&mut rng, VoronoiParams::default().sites),
+        )?;
+        let pixelate = Pixelate::new(
+            context.fullscreen_pass.clone(),
+            &context.size,
+            PixelateParams::default(),
+        )?;
+        let display_shader = context
+            .fullscreen_pass
+            .shader(include_str!("Display-FS.glsl"))?;
+        display_shader.set_label("recording display");
+        display_shader.uniform_1i(&display_shader.get_loc("FBO")?, 0);
         let egui_painter = Painter::new(gl.clone(), "", None, true)?;
         let egui_context = egui::Context::default();
         let egui_state = egui_winit::State::new(
             egui_context,
             egui::ViewportId::ROOT,
-            window,
+            context.get_window(),
             None,
             None,
             None,
         );
 
-        Ok(Self {
+        let mut handler = Self {
+            chain: DEFAULT_CHAIN.to_vec(),
             voronoi,
+            pixelate,
+            site_editor: SiteEditor::new(),
+            seed,
+            rng,
+            preset_path: "preset.toml".to_owned(),
+            recent_presets: RecentPresets::load(RECENT_PRESETS_PATH),
+            recorder: None,
+            recorder_config: RecorderConfig::default(),
+            clock_before_recording: None,
+            headless: false,
+            window_size: context.size,
+            fullscreen_pass: context.fullscreen_pass.clone(),
+            display_shader,
             egui_state,
             egui_painter,
-        })
+        };
+        if let Some(path) = config.preset {
+            handler.load_preset(&path)?;
+        }
+        if !config.chain.is_empty() {
+            handler.chain = config.chain;
+        }
+        if let Some(path) = config.palette {
+            handler.pixelate.set_palette(Palette::load(path)?);
+        }
+        if let Some(render) = config.render {
+            handler.headless = true;
+            handler.start_recording(context.gl.clone(), render)?;
+        }
+
+        Ok(handler)
+    }
+
+    ///
+    /// Adds the passes of every effect of the chain, the last one drawing to `screen`
+    ///
+    fn add_chain<'a>(&'a self, graph: &mut RenderGraph<'a>, screen: ResourceId, viewport: Viewport) {
+        let mut input = None;
+        for (index, &effect) in self.chain.iter().enumerate() {
+            let output = if index + 1 == self.chain.len() {
+                screen
+            } else {
+                // A minimized window has an empty viewport, which no framebuffer can have
+                let desc = FrameBufferDesc::new(viewport.width.max(1), viewport.height.max(1))
+                    .color(AttachmentDesc::texture(RGBA8));
+                graph.create(effect.name(), desc)
+            };
+
+            match effect {
+                EffectKind::Voronoi => {
+                    let voronoi = &self.voronoi;
+                    graph.add_pass("voronoi", &[], &[output], move |pass| {
+                        voronoi.apply(pass.frame_buffer(output), pass.viewport(output))
+                    });
+                }
+                EffectKind::Pixelate => self.pixelate.add_passes(graph, input, output),
+            }
+            input = Some(output);
+        }
+    }
+
+    ///
+    /// Renders the effects at the resolution of the recording until it is stopped, the caller
+    /// setting the clock to the time step
+    ///
+    fn start_recording(&mut self, gl: Rc<GlState>, config: RecorderConfig) -> Result<()> {
+        let recorder = Recorder::new(gl, config, self.pixelate.palette())?;
+        self.pixelate.resize(&recorder.config().resolution)?;
+        self.recorder = Some(recorder);
+        Ok(())
+    }
+
+    fn stop_recording(&mut self, context: &AppContext) -> Result<()> {
+        if let Some((mode, scale)) = self.clock_before_recording.take() {
+            context.clock.set_mode(mode);
+            context.clock.set_scale(scale);
+        }
+        self.pixelate.resize(&self.window_size)?;
+        if let Some(recorder) = self.recorder.take() {
+            recorder.finish()?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Writes the frame just recorded, stopping after the last one or on error
+    ///
+    fn capture(&mut self, context: &AppContext) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        let captured = recorder.capture();
+        if matches!(captured, Ok(false)) {
+            return;
+        }
+
+        let stopped = self.stop_recording(context);
+        let result = captured.and(stopped);
+        match result {
+            // Ends the run with the error instead of going through `handle_error`
+            Err(error) if self.headless => context.fail(error),
+            Err(error) => error!("Recording stopped: {error:?}"),
+            Ok(()) if self.headless => context.exit(),
+            Ok(()) => {}
+        }
+    }
+
+    fn recorder_ui(&mut self, ui: &mut egui::Ui, context: &AppContext) {
+        let recorded = self.recorder.as_ref().map(Recorder::frame);
+        ui.add_enabled_ui(recorded.is_none(), |ui| self.recorder_config.ui(ui));
+
+        match recorded {
+            Some(frames) => {
+                ui.label(format!("Recorded {frames} frames"));
+                if ui.button("Stop").clicked()
+                    && let Err(error) = self.stop_recording(context)
+                {
+                    error!("{error:?}");
+                }
+            }
+            None => {
+                if ui.button("Start").clicked() {
+                    let config = self.recorder_config.clone();
+                    let time_step = config.time_step;
+                    match self.start_recording(context.gl.clone(), config) {
+                        Ok(()) => {
+                            self.clock_before_recording =
+                                Some((context.clock.mode(), context.clock.scale()));
+                            // Each frame advances by exactly one time step of the video
+                            context.clock.set_mode(ClockMode::FixedStep(time_step));
+                            context.clock.set_scale(1.0);
+                            context.clock.resume();
+                        }
+                        Err(error) => error!("{error:?}"),
+                    }
+                }
+            }
+        }
+    }
+
+    fn preset(&self) -> Preset {
+        Preset {
+            version: PRESET_VERSION,
+            seed: self.seed,
+            chain: self.chain.clone(),
+            voronoi: VoronoiPreset {
+                sites: self.voronoi.points().iter().map(|point| [point.x, point.y]).collect(),
+                params: self.voronoi.params().clone(),
+            },
+            pixelate: PixelatePreset {
+                palette: self.pixelate.palette().clone(),
+                params: self.pixelate.params().clone(),
+            },
+        }
+    }
+
+    fn apply_preset(&mut self, preset: Preset) -> Result<()> {
+        if preset.voronoi.sites.is_empty() {
+            return Err(anyhow::anyhow!("Preset has no Voronoi site"));
+        }
+
+        self.seed = preset.seed;
+        self.rng = StdRng::seed_from_u64(preset.seed);
+        self.chain = preset.chain;
+
+        let sites = preset.voronoi.sites.iter().map(|[x, y]| vec2(*x, *y)).collect();
+        self.site_editor.replace(&mut self.voronoi, sites);
+        let mut params = preset.voronoi.params;
+        params.sites = self.voronoi.points().len();
+        self.voronoi.set_params(params)?;
+
+        self.pixelate.set_params(preset.pixelate.params)?;
+        self.pixelate.set_palette(preset.pixelate.palette);
+
+        Ok(())
+    }
+
+    fn load_preset(&mut self, path: &Path) -> Result<()> {
+        let preset = Preset::load(path)?;
+        self.apply_preset(preset)?;
+        self.remember_preset(path);
+        Ok(())
+    }
+
+    fn save_preset(&mut self, path: &Path) -> Result<()> {
+        self.preset().save(path)?;
+        self.remember_preset(path);
+        Ok(())
+    }
+
+    fn remember_preset(&mut self, path: &Path) {
+        self.preset_path = path.display().to_string();
+        self.recent_presets.push(path);
+        if let Err(error) = self.recent_presets.save(RECENT_PRESETS_PATH) {
+            warn!("Unable to save recent presets: {error:?}");
+        }
+    }
+
+    fn effects_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Chain");
+            let mut enabled = EffectKind::ALL.map(|effect| self.chain.contains(&effect));
+            let mut changed = false;
+            for (effect, enabled) in EffectKind::ALL.into_iter().zip(&mut enabled) {
+                // The last effect stays, an empty chain would draw nothing
+                let removable = !*enabled || self.chain.len() > 1;
+                changed |= ui
+                    .add_enabled(removable, Checkbox::new(enabled, effect.name()))
+                    .changed();
+            }
+            if changed {
+                // Always in the order of `ALL`, where no effect drops its input
+                self.chain = EffectKind::ALL
+                    .into_iter()
+                    .zip(enabled)
+                    .filter_map(|(effect, enabled)| enabled.then_some(effect))
+                    .collect();
+            }
+        });
+
+        if self.chain.contains(&EffectKind::Voronoi) {
+            egui::CollapsingHeader::new("Voronoi")
+                .default_open(true)
+                .show(ui, |ui| {
+                    let mut params = self.voronoi.params().clone();
+                    if params.ui(ui, "VoronoiParams") {
+                        if params.sites != self.voronoi.points().len() {
+                            let points = generate_random_vec2s(&mut self.rng, params.sites);
+                            self.site_editor.replace(&mut self.voronoi, points);
+                        }
+                        if let Err(error) = self.voronoi.set_params(params) {
+                            error!("{error:?}");
+                        }
+                    }
+                });
+        }
+        if self.chain.contains(&EffectKind::Pixelate) {
+            egui::CollapsingHeader::new("Pixelate")
+                .default_open(true)
+                .show(ui, |ui| {
+                    let mut params = self.pixelate.params().clone();
+                    if params.ui(ui, "PixelateParams")
+                        && let Err(error) = self.pixelate.set_params(params)
+                    {
+                        error!("{error:?}");
+                    }
+                });
+        }
+    }
+
+    fn presets_ui(&mut self, ui: &mut egui::Ui) {
+        ui.text_edit_singleline(&mut self.preset_path);
+
+        let mut load = None;
+        ui.horizontal(|ui| {
+            if ui.button("Save").clicked() {
+                let path = PathBuf::from(&self.preset_path);
+                if let Err(error) = self.save_preset(&path) {
+                    error!("{error:?}");
+                }
+            }
+            if ui.button("Load").clicked() {
+                load = Some(PathBuf::from(&self.preset_path));
+            }
+        });
+
+        if !self.recent_presets.paths().is_empty() {
+            ui.label("Recent");
+        }
+        for path in self.recent_presets.paths() {
+            if ui.button(path.display().to_string()).clicked() {
+                load = Some(path.clone());
+            }
+        }
+
+        if let Some(path) = load
+            && let Err(error) = self.load_preset(&path)
+        {
+            error!("{error:?}");
+        }
     }
 }
 
 impl HandleApp for AppHandler {
     fn render(&mut self, context: &AppContext) -> Result<()> {
         let size = [context.size.width, context.size.height];
-        self.voronoi.apply(0, 0, size[0] as i32, size[1] as i32)?;
+        let mut graph = RenderGraph::new(context.gl.clone());
+        let viewport = Viewport::new(0, 0, size[0] as i32, size[1] as i32);
+        let screen = graph.import_default("screen", viewport);
+        match &self.recorder {
+            Some(recorder) => {
+                let frame_buffer = recorder.frame_buffer();
+                let recording = graph.import("recording", frame_buffer);
+                self.add_chain(&mut graph, recording, frame_buffer.viewport());
+
+                let preview = letterbox(frame_buffer.viewport(), viewport);
+                let shader = &self.display_shader;
+                let fullscreen_pass = &self.fullscreen_pass;
+                graph.add_pass("recording display", &[recording], &[screen], move |pass| {
+                    shader.use_program();
+                    pass.texture(recording, 0)?.activate_texture(TEXTURE0);
+                    fullscreen_pass.draw(shader, pass.frame_buffer(screen), preview);
+                    Ok(())
+                });
+            }
+            None => self.add_chain(&mut graph, screen, viewport),
+        }
+        graph.execute(&context.render_targets, &context.profiler)?;
+        // A paused frame is the same image again, recording it would duplicate the last one
+        if context.clock.advanced() {
+            self.capture(context);
+        }
+
+        if self.headless {
+            return Ok(());
+        }
 
         let input = self.egui_state.take_egui_input(context.get_window());
-        let full_output = self.egui_state.egui_ctx().run(input, |ctx| {
+        let egui_ctx = self.egui_state.egui_ctx().clone();
+        let full_output = egui_ctx.run(input, |ctx| {
             SidePanel::left(Id::new("SidePanel"))
                 .resizable(true)
                 .show(ctx, |ui| {
-                    ui.label("Hello, World!");
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(self.site_editor.can_undo(), Button::new("Undo"))
+                            .clicked()
+                        {
+                            self.site_editor.undo(&mut self.voronoi);
+                        }
+                        if ui
+                            .add_enabled(self.site_editor.can_redo(), Button::new("Redo"))
+                            .clicked()
+                        {
+                            self.site_editor.redo(&mut self.voronoi);
+                        }
+                    });
+                    self.effects_ui(ui);
+                    ui.separator();
+                    ui.collapsing("Presets", |ui| self.presets_ui(ui));
+                    ui.collapsing("Recorder", |ui| self.recorder_ui(ui, context));
+                    ui.collapsing("Clock", |ui| context.clock.ui(ui));
+                    ui.collapsing("Profiler", |ui| context.profiler.ui(ui));
+                    ui.collapsing("Render targets", |ui| context.render_targets.ui(ui));
+                    ui.collapsing("GL state", |ui| context.gl.ui(ui));
+                    if let Some(program_cache) = context.gl.program_cache() {
+                        ui.collapsing("Program cache", |ui| program_cache.ui(ui));
+                    }
                     ui.allocate_space(ui.available_size());
                 });
         });
@@ -69,33 +489,60 @@ impl HandleApp for AppHandler {
             self.egui_state.egui_ctx().pixels_per_point(),
         );
 
-        self.egui_painter.paint_and_update_textures(
-            size,
-            self.egui_state.egui_ctx().pixels_per_point(),
-            &paint_jobs,
-            &full_output.textures_delta,
-        );
+        context.profiler.scope("egui", || {
+            // The painter enables scissoring and blending behind the state cache
+            context.gl.scope(|| {
+                self.egui_painter.paint_and_update_textures(
+                    size,
+                    self.egui_state.egui_ctx().pixels_per_point(),
+                    &paint_jobs,
+                    &full_output.textures_delta,
+                );
+            })
+        });
 
         Ok(())
     }
 
-    fn event(&mut self, window: &Window, event: &WindowEvent) {
-        if self.egui_state.on_window_event(window, event).consumed {
-            return;
-        }
+    fn event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        self.egui_state.on_window_event(window, event).consumed
     }
 
-    fn resize(&mut self, _size: &PhysicalSize<u32>) -> Result<()> {
-        Ok(())
+    fn resize(&mut self, size: &PhysicalSize<u32>) -> Result<()> {
+        self.window_size = *size;
+        // Recordings keep their resolution whatever the window does
+        if self.recorder.is_some() {
+            return Ok(());
+        }
+        self.pixelate.resize(size)
     }
 
     fn update(&mut self, context: &AppContext) -> Result<()> {
-        info!("delta time: {}s", context.get_delta_time());
+        if context.input.is_action_pressed("reseed") {
+            let points = generate_random_vec2s(&mut self.rng, self.voronoi.points().len());
+            self.site_editor.replace(&mut self.voronoi, points);
+        }
+        self.site_editor.update(&mut self.voronoi, &context.input);
+
+        if context.input.is_action_pressed("pause") {
+            context.clock.toggle_pause();
+        }
+        if context.input.is_action_pressed("step") {
+            context.clock.step();
+        }
 
         Ok(())
     }
 
     // TODO: Find out what i should do
+    fn exiting(&mut self, context: &AppContext) -> Result<()> {
+        // Closing the window mid recording still leaves a complete file
+        if self.recorder.is_some() {
+            self.stop_recording(context)?;
+        }
+        Ok(())
+    }
+
     fn handle_error(&self, error: anyhow::Error) {
         error!("{error:?}");
         panic!();
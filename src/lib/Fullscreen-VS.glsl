@@ -0,0 +1,13 @@
+#version 460
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+out vec2 vPos;
+
+// One triangle covering the whole viewport, generated without any vertex buffer
+void main() {
+    vPos = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+    gl_Position = vec4(vPos * 2.0 - 1.0, 0.0, 1.0);
+}
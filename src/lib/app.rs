@@ -1,23 +1,43 @@
-use std::{ffi::CStr, num::NonZeroU32, process::exit, sync::Arc, time::Instant};
+use std::{
+    any::{Any, TypeId},
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    ffi::CStr,
+    num::NonZeroU32,
+    path::PathBuf,
+    rc::Rc,
+};
 
 use anyhow::{Context, Error, Result};
 use glow::{COLOR_BUFFER_BIT, DEPTH_BUFFER_BIT, HasContext};
 use glutin::{
-    config::ConfigTemplateBuilder,
+    config::{Config, ConfigTemplateBuilder},
     context::{ContextAttributesBuilder, PossiblyCurrentContext},
     display::GetGlDisplay,
-    prelude::{GlDisplay, NotCurrentGlContext},
-    surface::{GlSurface, Surface, SurfaceAttributesBuilder, WindowSurface},
+    prelude::{GlConfig, GlDisplay, NotCurrentGlContext},
+    surface::{GlSurface, Surface, SurfaceAttributesBuilder, SwapInterval, WindowSurface},
 };
 use glutin_winit::DisplayBuilder;
-use log::{error, info};
+use log::{error, info, warn};
+
+use crate::{
+    clock::Clock,
+    debug::DebugOutput,
+    fullscreen_pass::{FullscreenPass, Viewport},
+    gl_state::GlState,
+    input::{ActionMap, Input},
+    profiler::Profiler,
+    program_cache::ProgramCache,
+    render_target_pool::RenderTargetPool,
+    shader_buffer::{FRAME_BINDING, FrameUniforms, UniformBuffer},
+};
 use winit::{
     application::ApplicationHandler,
     dpi::PhysicalSize,
     event::WindowEvent,
     event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
     raw_window_handle::HasWindowHandle,
-    window::Window,
+    window::{Fullscreen, Window},
 };
 
 pub trait HandleApp {
@@ -25,56 +45,159 @@ pub trait HandleApp {
     fn render(&mut self, context: &AppContext) -> Result<()>;
     fn resize(&mut self, size: &PhysicalSize<u32>) -> Result<()>;
     fn handle_error(&self, error: Error);
-    fn event(&mut self, window: &Window, event: &WindowEvent);
+    ///
+    /// Returns whether the event was consumed, in which case it is not used for input tracking
+    ///
+    fn event(&mut self, window: &Window, event: &WindowEvent) -> bool;
+    ///
+    /// Called once when the event loop stops, while the window and GL context still exist
+    ///
+    fn exiting(&mut self, context: &AppContext) -> Result<()>;
+}
+
+///
+/// Values of any type shared between the application and its handler, keyed by type
+///
+#[derive(Default)]
+pub struct UserData {
+    values: HashMap<TypeId, Box<dyn Any>>,
 }
 
-type HandlerCreator = fn(
-    gl: Arc<glow::Context>,
-    window: &Window,
-    size: &PhysicalSize<u32>,
-) -> Result<Box<dyn HandleApp>>;
+impl UserData {
+    pub fn insert<T: 'static>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok())
+            .map(|old| *old)
+    }
+
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.values
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_mut())
+    }
+
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        self.values
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+}
+
+///
+/// Everything a handler factory needs to build the handler once the window and GL context exist
+///
+pub struct InitContext<'a> {
+    window: &'a Window,
+    pub size: PhysicalSize<u32>,
+    pub gl: Rc<GlState>,
+    pub fullscreen_pass: Rc<FullscreenPass>,
+    pub user_data: &'a mut UserData,
+}
+
+impl InitContext<'_> {
+    pub fn get_window(&self) -> &Window {
+        self.window
+    }
+}
 
 pub struct AppContext {
     window: Window,
-    last_time: Instant,
+    exit_requested: Cell<bool>,
+    /// Error `App::run` returns once the event loop stops
+    error: RefCell<Option<Error>>,
     pub size: PhysicalSize<u32>,
-    pub gl: Arc<glow::Context>,
+    /// Ticked at the start of every frame, before `HandleApp::update`
+    pub clock: Clock,
+    pub gl: Rc<GlState>,
+    pub fullscreen_pass: Rc<FullscreenPass>,
+    pub user_data: UserData,
+    pub input: Input,
+    pub profiler: Profiler,
+    pub render_targets: RenderTargetPool,
 }
 
 impl AppContext {
-    pub fn get_delta_time(&self) -> f32 {
-        self.last_time.elapsed().as_secs_f32()
-    }
-
     pub fn get_window(&self) -> &Window {
         &self.window
     }
+
+    pub fn user_data<T: 'static>(&self) -> Option<&T> {
+        self.user_data.get()
+    }
+
+    ///
+    /// Stops the event loop once the current frame is done, `App::run` then returns
+    ///
+    pub fn exit(&self) {
+        self.exit_requested.set(true);
+    }
+
+    ///
+    /// Stops the event loop once the current frame is done, `App::run` then returns `error`
+    ///
+    pub fn fail(&self, error: Error) {
+        self.error.replace(Some(error));
+        self.exit_requested.set(true);
+    }
 }
 
-struct Runtime {
+struct Runtime<H: HandleApp> {
     context: AppContext,
-    handler: Box<dyn HandleApp>,
+    handler: H,
     current_context: PossiblyCurrentContext,
     surface: Surface<WindowSurface>,
+    frame_uniforms: UniformBuffer<FrameUniforms>,
+    visible: bool,
 }
 
-impl Runtime {
-    fn new(event_loop: &ActiveEventLoop, handler_creator: HandlerCreator) -> Result<Self> {
-        let attributes = Window::default_attributes().with_title("Rust Playground");
+impl<H: HandleApp> Runtime<H> {
+    ///
+    /// Creates the window, the GL context and the handler
+    ///
+    fn new<F>(
+        event_loop: &ActiveEventLoop,
+        handler_creator: F,
+        options: &mut AppOptions,
+    ) -> Result<Self>
+    where
+        F: FnOnce(&mut InitContext) -> Result<H>,
+    {
+        let mut attributes = Window::default_attributes()
+            .with_title("Rust Playground")
+            .with_visible(!options.hidden);
+        if let Some(size) = options.window_size {
+            attributes = attributes.with_inner_size(size);
+        }
+        if options.fullscreen {
+            attributes = attributes.with_fullscreen(Some(Fullscreen::Borderless(None)));
+        }
 
+        // Multisampling is left out of the template so that configs without it remain
+        // available as a fallback
         let template = ConfigTemplateBuilder::new()
             .with_alpha_size(8)
             .with_depth_size(24)
             .with_stencil_size(8);
+        let samples = options.samples;
 
         let display_builder = DisplayBuilder::new().with_window_attributes(Some(attributes));
 
         let (window, config) = display_builder
             .build(event_loop, template, |configs| {
-                configs.reduce(|a, _b| a).unwrap()
+                select_config(configs, samples)
             })
             .expect("Failed to create window and config");
 
+        info!("Selected a surface with {} samples", config.num_samples());
+
         let window = window.expect("Window creation failed");
         let window_handle = window.window_handle()?;
         let display = config.display();
@@ -104,44 +227,150 @@ impl Runtime {
             .make_current(&surface)
             .context("Failed to make context current")?;
 
+        if let Some(vsync) = options.vsync {
+            let interval = if vsync {
+                SwapInterval::Wait(NonZeroU32::MIN)
+            } else {
+                SwapInterval::DontWait
+            };
+            if let Err(error) = surface.set_swap_interval(&context, interval) {
+                warn!("Unable to set the swap interval: {error}");
+            }
+        }
+
         let gl;
         unsafe {
             let get_proc_address = |s: &CStr| display.get_proc_address(s) as *const _;
-            gl = Arc::new(glow::Context::from_loader_function_cstr(get_proc_address));
-            gl.viewport(0, 0, size.width as i32, size.height as i32);
-            gl.clear_color(0.2, 0.2, 0.2, 1.0);
+            let mut context = glow::Context::from_loader_function_cstr(get_proc_address);
+            options.debug_output.install(&mut context);
+            gl = Rc::new(GlState::new(context, get_proc_address));
+            gl.context().clear_color(0.2, 0.2, 0.2, 1.0);
+        }
+        gl.set_viewport(Viewport::new(0, 0, size.width as i32, size.height as i32));
+
+        if let Some(dir) = &options.program_cache {
+            match ProgramCache::new(&gl, dir.clone()) {
+                Ok(cache) => gl.set_program_cache(cache),
+                Err(error) => warn!("Program binaries will not be cached: {error:?}"),
+            }
         }
 
         info!("Initialized the window");
 
+        let mut app_context = AppContext {
+            window,
+            size,
+            gl: gl.clone(),
+            fullscreen_pass: Rc::new(FullscreenPass::new(gl.clone())?),
+            exit_requested: Cell::new(false),
+            error: RefCell::new(None),
+            clock: Clock::default(),
+            user_data: UserData::default(),
+            input: Input::new(size, options.actions.clone()),
+            profiler: Profiler::new(gl.clone()),
+            render_targets: RenderTargetPool::new(gl.clone()),
+        };
+
+        let handler = handler_creator(&mut InitContext {
+            window: &app_context.window,
+            size,
+            gl,
+            fullscreen_pass: app_context.fullscreen_pass.clone(),
+            user_data: &mut options.user_data,
+        })?;
+
+        let frame_uniforms =
+            UniformBuffer::new(app_context.gl.clone(), &FrameUniforms::default())?;
+        frame_uniforms.set_label("frame uniforms");
+
+        app_context.user_data = std::mem::take(&mut options.user_data);
+        app_context.clock = std::mem::take(&mut options.clock);
+        // Window, context and handler creation are not part of the first frame
+        app_context.clock.restart();
+
         return Ok(Self {
-            handler: handler_creator(gl.clone(), &window, &size)?,
+            handler,
             surface,
             current_context: context,
-            context: AppContext {
-                window,
-                size,
-                gl,
-                last_time: Instant::now(),
-            },
+            context: app_context,
+            frame_uniforms,
+            visible: !options.hidden,
         });
     }
 
+    ///
+    /// Uploads the `Frame` uniform block shared by every shader
+    ///
+    fn update_frame_uniforms(&mut self) {
+        let size = self.context.size;
+        let mouse = self
+            .context
+            .input
+            .cursor_position()
+            .map_or([0.0, 0.0], |position| {
+                [position.x, size.height as f32 - position.y]
+            });
+
+        let clock = &self.context.clock;
+        self.frame_uniforms.update(&FrameUniforms {
+            resolution: [size.width as f32, size.height as f32],
+            mouse,
+            time: clock.time() as f32,
+            delta_time: clock.delta(),
+            frame_index: clock.frame_index() as u32,
+        });
+        self.frame_uniforms.bind(FRAME_BINDING);
+    }
+
     fn render(&mut self) -> Result<()> {
-        self.handler.update(&self.context)?;
+        self.context.profiler.begin_frame();
+        self.context.clock.tick();
+        self.update_frame_uniforms();
 
-        unsafe {
-            self.context.gl.clear(DEPTH_BUFFER_BIT | COLOR_BUFFER_BIT);
-        }
+        self.context
+            .profiler
+            .scope("update", || self.handler.update(&self.context))?;
+
+        self.context.profiler.scope("render", || {
+            unsafe {
+                self.context.gl.context().clear(DEPTH_BUFFER_BIT | COLOR_BUFFER_BIT);
+            }
 
-        self.handler.render(&self.context)?;
+            self.handler.render(&self.context)
+        })?;
 
         self.surface.swap_buffers(&self.current_context)?;
+        self.context.profiler.end_frame();
+        self.context.render_targets.end_frame();
+        self.context.gl.end_frame();
 
-        self.context.last_time = Instant::now();
+        self.context.input.end_frame();
         Ok(())
     }
 
+    ///
+    /// Renders a frame, then stops the event loop if the handler asked to exit
+    ///
+    fn frame(&mut self, event_loop: &ActiveEventLoop) {
+        if let Err(err) = self.render() {
+            // TODO: Handle errors correctly
+            self.handler.handle_error(err);
+        }
+        if self.context.exit_requested.get() {
+            event_loop.exit();
+        }
+    }
+
+    ///
+    /// Lets the handler finish its work, keeping the first error for `App::run`
+    ///
+    fn exiting(&mut self) {
+        if let Err(err) = self.handler.exiting(&self.context) {
+            error!("{err:?}");
+            self.context.error.borrow_mut().get_or_insert(err);
+        }
+    }
+
     fn resize(&mut self, size: PhysicalSize<u32>) -> Result<()> {
         self.context.size = size;
 
@@ -151,48 +380,251 @@ impl Runtime {
     }
 }
 
-pub struct App {
-    state: AppState,
+///
+/// Config with the fewest samples that still has at least `samples`, or one without
+/// multisampling when the display offers none with that many
+///
+fn select_config(configs: Box<dyn Iterator<Item = Config> + '_>, samples: u8) -> Config {
+    let configs: Vec<Config> = configs.collect();
+    let single_sampled = || {
+        configs
+            .iter()
+            .find(|config| config.num_samples() <= 1)
+            .unwrap_or(&configs[0])
+            .clone()
+    };
+
+    if samples <= 1 {
+        return single_sampled();
+    }
+
+    match configs
+        .iter()
+        .filter(|config| config.num_samples() >= samples)
+        .min_by_key(|config| config.num_samples())
+    {
+        Some(config) => config.clone(),
+        None => {
+            warn!("No surface with {samples} samples available, multisampling is disabled");
+            single_sampled()
+        }
+    }
+}
+
+pub struct App<H, F>
+where
+    H: HandleApp,
+    F: FnOnce(&mut InitContext) -> Result<H>,
+{
+    state: AppState<H, F>,
 }
 
-impl App {
-    pub fn new(handler_creator: HandlerCreator) -> Self {
-        let state = AppState::Uninitialized(handler_creator);
+impl<H, F> App<H, F>
+where
+    H: HandleApp,
+    F: FnOnce(&mut InitContext) -> Result<H>,
+{
+    pub fn new(handler_creator: F) -> Self {
+        let state = AppState::Uninitialized {
+            handler_creator: Some(handler_creator),
+            options: AppOptions::default(),
+            error: None,
+        };
         return Self { state };
     }
 
+    ///
+    /// Stores a value that the handler can later retrieve by type from the init or app context
+    ///
+    pub fn with_user_data<T: 'static>(mut self, value: T) -> Self {
+        if let AppState::Uninitialized { options, .. } = &mut self.state {
+            options.user_data.insert(value);
+        }
+        self
+    }
+
+    ///
+    /// Sets the action bindings used by the input state
+    ///
+    pub fn with_action_map(mut self, action_map: ActionMap) -> Self {
+        if let AppState::Uninitialized { options, .. } = &mut self.state {
+            options.actions = action_map;
+        }
+        self
+    }
+
+    ///
+    /// Configures how GL debug messages are forwarded to the logger
+    ///
+    pub fn with_debug_output(mut self, debug_output: DebugOutput) -> Self {
+        if let AppState::Uninitialized { options, .. } = &mut self.state {
+            options.debug_output = debug_output;
+        }
+        self
+    }
+
+    ///
+    /// Requests a multisampled window surface, the driver may give fewer samples than asked
+    ///
+    pub fn with_multisampling(mut self, samples: u8) -> Self {
+        if let AppState::Uninitialized { options, .. } = &mut self.state {
+            options.samples = samples;
+        }
+        self
+    }
+
+    ///
+    /// Sets the initial inner size of the window
+    ///
+    pub fn with_window_size(mut self, size: PhysicalSize<u32>) -> Self {
+        if let AppState::Uninitialized { options, .. } = &mut self.state {
+            options.window_size = Some(size);
+        }
+        self
+    }
+
+    ///
+    /// Opens the window borderless fullscreen on the current monitor
+    ///
+    pub fn with_fullscreen(mut self, fullscreen: bool) -> Self {
+        if let AppState::Uninitialized { options, .. } = &mut self.state {
+            options.fullscreen = fullscreen;
+        }
+        self
+    }
+
+    ///
+    /// Waits for the vertical blank before presenting when `true`, presents immediately otherwise
+    ///
+    pub fn with_vsync(mut self, vsync: bool) -> Self {
+        if let AppState::Uninitialized { options, .. } = &mut self.state {
+            options.vsync = Some(vsync);
+        }
+        self
+    }
+
+    ///
+    /// Never shows the window, for handlers rendering offscreen
+    ///
+    /// Hidden windows do not get redraw requests on every platform, so their frames are
+    /// rendered whenever the event loop is idle instead.
+    ///
+    pub fn with_hidden_window(mut self) -> Self {
+        if let AppState::Uninitialized { options, .. } = &mut self.state {
+            options.hidden = true;
+        }
+        self
+    }
+
+    ///
+    /// Replaces the real time clock, for example by one with a fixed step for offline export
+    ///
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        if let AppState::Uninitialized { options, .. } = &mut self.state {
+            options.clock = clock;
+        }
+        self
+    }
+
+    ///
+    /// Keeps linked program binaries in `dir` to skip compiling them on the next launches
+    ///
+    pub fn with_program_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        if let AppState::Uninitialized { options, .. } = &mut self.state {
+            options.program_cache = Some(dir.into());
+        }
+        self
+    }
+
     pub fn run(&mut self) -> Result<()> {
         let event_loop = EventLoop::new()?;
         event_loop.set_control_flow(ControlFlow::Poll);
 
         event_loop.run_app(&mut self.state)?;
 
-        Ok(())
+        let error = match &mut self.state {
+            AppState::Uninitialized { error, .. } => error.take(),
+            AppState::Initialized(runtime) => runtime.context.error.take(),
+        };
+        match error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
     }
 }
 
-enum AppState {
-    Uninitialized(HandlerCreator),
-    Initialized(Runtime),
+///
+/// Settings collected by the `App` builder until the runtime is created
+///
+#[derive(Default)]
+struct AppOptions {
+    user_data: UserData,
+    actions: ActionMap,
+    debug_output: DebugOutput,
+    /// Samples per pixel of the window surface, 0 for no multisampling
+    samples: u8,
+    /// Directory of the program binary cache, `None` to always compile from source
+    program_cache: Option<PathBuf>,
+    /// Initial inner size of the window, `None` for the platform default
+    window_size: Option<PhysicalSize<u32>>,
+    fullscreen: bool,
+    /// `None` keeps the swap interval of the driver
+    vsync: Option<bool>,
+    hidden: bool,
+    clock: Clock,
 }
 
-impl ApplicationHandler for AppState {
-    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        if let Self::Uninitialized(handler_creator) = self {
-            let app = Runtime::new(event_loop, *handler_creator);
+enum AppState<H: HandleApp, F> {
+    Uninitialized {
+        handler_creator: Option<F>,
+        options: AppOptions,
+        /// Why the runtime could not be created
+        error: Option<Error>,
+    },
+    Initialized(Runtime<H>),
+}
 
-            if let Ok(app) = app {
-                *self = Self::Initialized(app);
-                return;
+impl<H, F> ApplicationHandler for AppState<H, F>
+where
+    H: HandleApp,
+    F: FnOnce(&mut InitContext) -> Result<H>,
+{
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if let Self::Uninitialized {
+            handler_creator,
+            options,
+            error,
+        } = self
+            && let Some(handler_creator) = handler_creator.take()
+        {
+            match Runtime::new(event_loop, handler_creator, options) {
+                Ok(app) => *self = Self::Initialized(app),
+                Err(err) => {
+                    error!("{err:?}");
+                    *error = Some(err);
+                    event_loop.exit();
+                }
             }
+        }
+    }
 
-            error!("{:?}", app.err());
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        if let Self::Initialized(app) = self
+            && !app.visible
+        {
+            app.frame(event_loop);
+        }
+    }
+
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        if let Self::Initialized(app) = self {
+            app.exiting();
         }
     }
 
     fn window_event(
         &mut self,
-        _event_loop: &ActiveEventLoop,
+        event_loop: &ActiveEventLoop,
         _window_id: winit::window::WindowId,
         event: WindowEvent,
     ) {
@@ -200,17 +632,15 @@ impl ApplicationHandler for AppState {
             return;
         };
 
-        app.handler.event(&app.context.window, &event);
+        let consumed = app.handler.event(&app.context.window, &event);
+        app.context.input.handle_event(&event, consumed);
 
         match event {
             WindowEvent::CloseRequested => {
-                exit(0);
+                event_loop.exit();
             }
             WindowEvent::RedrawRequested => {
-                if let Err(err) = app.render() {
-                    // TODO: Handle errors correctly
-                    app.handler.handle_error(err);
-                }
+                app.frame(event_loop);
                 app.context.window.request_redraw();
             }
             WindowEvent::Resized(size) => {
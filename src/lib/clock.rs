@@ -0,0 +1,325 @@
+use std::{cell::Cell, time::Instant};
+
+use egui::{Button, Slider};
+
+/// Step of a single frame advanced while paused in real time
+const DEFAULT_STEP: f32 = 1.0 / 60.0;
+
+///
+/// Where the delta of every frame comes from
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockMode {
+    /// Wall clock time elapsed since the previous frame
+    RealTime,
+    /// Same number of seconds every frame, whatever the time the frame took, for offline export
+    FixedStep(f32),
+    /// Only the seconds given to `advance` since the previous frame, for tests
+    Manual,
+}
+
+///
+/// Time of the application, ticked once per frame by the runtime
+///
+/// Everything animated reads its time from here, through `AppContext::clock` or the `Frame`
+/// uniform block, so that pausing, stepping and scaling apply everywhere at once.
+///
+/// ```ignore
+/// context.clock.pause();
+/// context.clock.step();
+/// context.clock.set_scale(0.5);
+/// ```
+///
+pub struct Clock {
+    mode: Cell<ClockMode>,
+    last_tick: Cell<Instant>,
+    /// Seconds given to `advance` and not yet ticked
+    pending: Cell<f32>,
+    time: Cell<f64>,
+    delta: Cell<f32>,
+    /// Ticks so far
+    frames: Cell<u64>,
+    paused: Cell<bool>,
+    step_requested: Cell<bool>,
+    /// Whether the current frame moved the time forward, paused frames not stepped excepted
+    advanced: Cell<bool>,
+    scale: Cell<f32>,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new(ClockMode::RealTime)
+    }
+}
+
+impl Clock {
+    pub fn new(mode: ClockMode) -> Self {
+        Self {
+            mode: Cell::new(mode),
+            last_tick: Cell::new(Instant::now()),
+            pending: Cell::new(0.0),
+            time: Cell::new(0.0),
+            delta: Cell::new(0.0),
+            frames: Cell::new(0),
+            paused: Cell::new(false),
+            step_requested: Cell::new(false),
+            advanced: Cell::new(false),
+            scale: Cell::new(1.0),
+        }
+    }
+
+    ///
+    /// Measures the next real time delta from now, so that the time spent before the first
+    /// frame is not part of it
+    ///
+    pub fn restart(&self) {
+        self.last_tick.set(Instant::now());
+    }
+
+    ///
+    /// Starts a new frame, advancing the time by the delta of the mode unless paused
+    ///
+    pub fn tick(&self) {
+        let now = Instant::now();
+        let elapsed = now
+            .duration_since(self.last_tick.replace(now))
+            .as_secs_f32();
+        let step = match self.mode.get() {
+            ClockMode::RealTime => elapsed,
+            ClockMode::FixedStep(step) => step,
+            ClockMode::Manual => self.pending.take(),
+        };
+
+        let delta = if !self.paused.get() {
+            Some(step)
+        } else if self.step_requested.take() {
+            // The wall clock keeps going while paused, a step is one nominal frame instead
+            Some(match self.mode.get() {
+                ClockMode::RealTime => DEFAULT_STEP,
+                _ => step,
+            })
+        } else {
+            None
+        };
+        self.advanced.set(delta.is_some());
+        let delta = delta.unwrap_or(0.0);
+
+        self.delta.set(delta * self.scale.get());
+        self.time.set(self.time.get() + self.delta.get() as f64);
+        self.frames.set(self.frames.get() + 1);
+    }
+
+    ///
+    /// Seconds elapsed since the start, scaled and without the paused frames
+    ///
+    pub fn time(&self) -> f64 {
+        self.time.get()
+    }
+
+    ///
+    /// Seconds added to `time` by the current frame, 0 while paused
+    ///
+    pub fn delta(&self) -> f32 {
+        self.delta.get()
+    }
+
+    ///
+    /// Index of the current frame, 0 during the first one, paused frames included
+    ///
+    pub fn frame_index(&self) -> u64 {
+        self.frames.get().saturating_sub(1)
+    }
+
+    ///
+    /// Whether the current frame moved the time forward, false while paused unless stepping
+    ///
+    pub fn advanced(&self) -> bool {
+        self.advanced.get()
+    }
+
+    pub fn mode(&self) -> ClockMode {
+        self.mode.get()
+    }
+
+    ///
+    /// Changes how the next frames advance, the time keeps going from where it is
+    ///
+    pub fn set_mode(&self, mode: ClockMode) {
+        self.mode.set(mode);
+        self.pending.set(0.0);
+    }
+
+    ///
+    /// Seconds the next tick adds in manual mode, before scaling
+    ///
+    pub fn advance(&self, seconds: f32) {
+        self.pending.set(self.pending.get() + seconds);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.get()
+    }
+
+    pub fn pause(&self) {
+        self.paused.set(true);
+    }
+
+    pub fn resume(&self) {
+        self.paused.set(false);
+        self.step_requested.set(false);
+    }
+
+    pub fn toggle_pause(&self) {
+        if self.is_paused() {
+            self.resume();
+        } else {
+            self.pause();
+        }
+    }
+
+    ///
+    /// Advances the next frame by one step while paused
+    ///
+    pub fn step(&self) {
+        self.step_requested.set(true);
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale.get()
+    }
+
+    ///
+    /// Multiplies every delta, 0.5 playing at half speed
+    ///
+    pub fn set_scale(&self, scale: f32) {
+        self.scale.set(scale.max(0.0));
+    }
+
+    ///
+    /// Draws the time and the pause, step and scale controls
+    ///
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        egui::Grid::new("Clock").num_columns(2).show(ui, |ui| {
+            ui.label("Time");
+            ui.label(format!("{:.3} s", self.time()));
+            ui.end_row();
+            ui.label("Delta");
+            ui.label(format!("{:.2} ms", self.delta() * 1000.0));
+            ui.end_row();
+            ui.label("Frame");
+            ui.label(self.frame_index().to_string());
+            ui.end_row();
+            ui.label("Mode");
+            ui.label(match self.mode() {
+                ClockMode::RealTime => "Real time".to_owned(),
+                ClockMode::FixedStep(step) => format!("Fixed step of {:.2} ms", step * 1000.0),
+                ClockMode::Manual => "Manual".to_owned(),
+            });
+            ui.end_row();
+            ui.label("Scale");
+            let mut scale = self.scale();
+            if ui.add(Slider::new(&mut scale, 0.0..=4.0)).changed() {
+                self.set_scale(scale);
+            }
+            ui.end_row();
+        });
+        ui.horizontal(|ui| {
+            let label = if self.is_paused() { "Resume" } else { "Pause" };
+            if ui.button(label).clicked() {
+                self.toggle_pause();
+            }
+            if ui
+                .add_enabled(self.is_paused(), Button::new("Step"))
+                .clicked()
+            {
+                self.step();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_advances_by_the_given_seconds() {
+        let clock = Clock::new(ClockMode::Manual);
+        clock.advance(0.25);
+        clock.advance(0.25);
+        clock.tick();
+        assert_eq!(clock.delta(), 0.5);
+        assert_eq!(clock.time(), 0.5);
+        assert_eq!(clock.frame_index(), 0);
+
+        clock.tick();
+        assert_eq!(clock.delta(), 0.0);
+        assert_eq!(clock.time(), 0.5);
+        assert_eq!(clock.frame_index(), 1);
+    }
+
+    #[test]
+    fn fixed_step_ignores_the_wall_clock() {
+        let clock = Clock::new(ClockMode::FixedStep(0.125));
+        for _ in 0..4 {
+            clock.tick();
+        }
+        assert_eq!(clock.delta(), 0.125);
+        assert_eq!(clock.time(), 0.5);
+        assert_eq!(clock.frame_index(), 3);
+    }
+
+    #[test]
+    fn pause_stops_the_time_but_not_the_frames() {
+        let clock = Clock::new(ClockMode::FixedStep(0.125));
+        clock.tick();
+        clock.pause();
+        clock.tick();
+        assert!(!clock.advanced());
+        assert_eq!(clock.delta(), 0.0);
+        assert_eq!(clock.time(), 0.125);
+        assert_eq!(clock.frame_index(), 1);
+
+        clock.resume();
+        clock.tick();
+        assert!(clock.advanced());
+        assert_eq!(clock.time(), 0.25);
+    }
+
+    #[test]
+    fn step_advances_one_frame_while_paused() {
+        let clock = Clock::new(ClockMode::FixedStep(0.125));
+        clock.pause();
+        clock.step();
+        clock.tick();
+        assert!(clock.advanced());
+        assert_eq!(clock.delta(), 0.125);
+
+        clock.tick();
+        assert!(!clock.advanced());
+        assert_eq!(clock.time(), 0.125);
+    }
+
+    #[test]
+    fn real_time_step_is_one_nominal_frame() {
+        let clock = Clock::new(ClockMode::RealTime);
+        clock.pause();
+        clock.step();
+        clock.tick();
+        assert_eq!(clock.delta(), DEFAULT_STEP);
+    }
+
+    #[test]
+    fn scale_multiplies_the_delta() {
+        let clock = Clock::new(ClockMode::FixedStep(0.125));
+        clock.set_scale(2.0);
+        clock.tick();
+        assert_eq!(clock.delta(), 0.25);
+
+        clock.set_scale(-1.0);
+        assert_eq!(clock.scale(), 0.0);
+        clock.tick();
+        assert_eq!(clock.delta(), 0.0);
+        assert_eq!(clock.time(), 0.25);
+    }
+}
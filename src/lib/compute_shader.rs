@@ -0,0 +1,136 @@
+use std::{
+    ops::{BitOr, Deref},
+    rc::Rc,
+};
+
+use anyhow::Result;
+use glow::{DISPATCH_INDIRECT_BUFFER, HasContext};
+use log::info;
+
+use crate::{gl_state::GlState, shader::Shader};
+
+///
+/// Set of `glMemoryBarrier` bits, combined with `|`
+///
+/// Each bit makes the commands issued after the barrier see the incoherent writes
+/// (image stores, storage buffer writes, atomics) of the commands issued before it,
+/// for one way of reading the memory.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Barrier(pub u32);
+
+impl Barrier {
+    /// Image load, store and atomics
+    pub const IMAGE_ACCESS: Barrier = Barrier(glow::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+    /// Sampling textures
+    pub const TEXTURE_FETCH: Barrier = Barrier(glow::TEXTURE_FETCH_BARRIER_BIT);
+    /// Shader storage blocks
+    pub const STORAGE_BUFFER: Barrier = Barrier(glow::SHADER_STORAGE_BARRIER_BIT);
+    /// Uniform blocks
+    pub const UNIFORM: Barrier = Barrier(glow::UNIFORM_BARRIER_BIT);
+    /// Vertex attributes read from buffers
+    pub const VERTEX_ATTRIB: Barrier = Barrier(glow::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
+    /// Index buffers
+    pub const ELEMENT_ARRAY: Barrier = Barrier(glow::ELEMENT_ARRAY_BARRIER_BIT);
+    /// Indirect draw and dispatch arguments
+    pub const COMMAND: Barrier = Barrier(glow::COMMAND_BARRIER_BIT);
+    /// Reads and writes through frame buffer attachments
+    pub const FRAMEBUFFER: Barrier = Barrier(glow::FRAMEBUFFER_BARRIER_BIT);
+    /// Buffer reads and writes from the CPU side, like sub data updates and read backs
+    pub const BUFFER_UPDATE: Barrier = Barrier(glow::BUFFER_UPDATE_BARRIER_BIT);
+    pub const ALL: Barrier = Barrier(glow::ALL_BARRIER_BITS);
+}
+
+impl BitOr for Barrier {
+    type Output = Barrier;
+
+    fn bitor(self, other: Barrier) -> Barrier {
+        Barrier(self.0 | other.0)
+    }
+}
+
+///
+/// Waits for incoherent writes before the accesses described by `barrier`
+///
+pub fn memory_barrier(gl: &GlState, barrier: Barrier) {
+    unsafe {
+        gl.context().memory_barrier(barrier.0);
+    }
+}
+
+///
+/// Program made of a single compute shader
+///
+/// Dereferences to its `Shader` for uniforms and labels.
+///
+pub struct ComputeShader {
+    shader: Shader,
+    work_group_size: [u32; 3],
+}
+
+impl ComputeShader {
+    pub fn new(gl: Rc<GlState>, source: &str) -> Result<Self> {
+        let shader = Shader::compute(gl, source)?;
+        let work_group_size = shader.gl().compute_work_group_size(shader.id)?;
+
+        info!(
+            "Initialized compute shader {:?} with work groups of {work_group_size:?}",
+            shader.id
+        );
+
+        Ok(Self {
+            shader,
+            work_group_size,
+        })
+    }
+
+    ///
+    /// `local_size_x`, `local_size_y` and `local_size_z` declared by the shader
+    ///
+    pub fn work_group_size(&self) -> [u32; 3] {
+        self.work_group_size
+    }
+
+    ///
+    /// Runs `x` by `y` by `z` work groups
+    ///
+    pub fn dispatch(&self, x: u32, y: u32, z: u32) {
+        self.shader.use_program();
+        unsafe {
+            self.shader.gl().context().dispatch_compute(x, y, z);
+        }
+    }
+
+    ///
+    /// Runs enough work groups to cover `width` by `height` by `depth` invocations
+    ///
+    pub fn dispatch_size(&self, width: u32, height: u32, depth: u32) {
+        let [x, y, z] = self.work_group_size;
+        self.dispatch(width.div_ceil(x), height.div_ceil(y), depth.div_ceil(z));
+    }
+
+    ///
+    /// Runs the number of work groups stored as three `u32` at `offset` bytes in `buffer`
+    ///
+    /// The buffer is usually written by a previous shader, which needs a `Barrier::COMMAND`
+    /// between the two.
+    ///
+    pub fn dispatch_indirect(&self, buffer: glow::Buffer, offset: usize) {
+        self.shader.use_program();
+        let gl = self.shader.gl();
+        unsafe {
+            gl.context()
+                .bind_buffer(DISPATCH_INDIRECT_BUFFER, Some(buffer));
+            gl.context().dispatch_compute_indirect(offset as i32);
+            gl.context().bind_buffer(DISPATCH_INDIRECT_BUFFER, None);
+        }
+    }
+}
+
+impl Deref for ComputeShader {
+    type Target = Shader;
+
+    fn deref(&self) -> &Shader {
+        &self.shader
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use glow::{
+    DEBUG_OUTPUT, DEBUG_OUTPUT_SYNCHRONOUS, DEBUG_SEVERITY_HIGH, DEBUG_SEVERITY_LOW,
+    DEBUG_SEVERITY_MEDIUM, DEBUG_SEVERITY_NOTIFICATION, DEBUG_SOURCE_API, DEBUG_SOURCE_APPLICATION,
+    DEBUG_SOURCE_OTHER, DEBUG_SOURCE_SHADER_COMPILER, DEBUG_SOURCE_THIRD_PARTY,
+    DEBUG_SOURCE_WINDOW_SYSTEM, DEBUG_TYPE_DEPRECATED_BEHAVIOR, DEBUG_TYPE_ERROR,
+    DEBUG_TYPE_MARKER, DEBUG_TYPE_OTHER, DEBUG_TYPE_PERFORMANCE, DEBUG_TYPE_POP_GROUP,
+    DEBUG_TYPE_PORTABILITY, DEBUG_TYPE_PUSH_GROUP, DEBUG_TYPE_UNDEFINED_BEHAVIOR, HasContext,
+};
+use log::{Level, info, log};
+
+use crate::gl_state::GlState;
+
+///
+/// Settings for the GL debug output forwarded to the `log` crate
+///
+#[derive(Clone)]
+pub struct DebugOutput {
+    pub enabled: bool,
+    /// Messages with these IDs are never logged
+    pub ignored_ids: HashSet<u32>,
+    /// Log a repeated message only the first time, then at every power of ten
+    pub suppress_duplicates: bool,
+    /// Report messages from the thread and call that caused them, at a performance cost
+    pub synchronous: bool,
+}
+
+impl Default for DebugOutput {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ignored_ids: HashSet::new(),
+            suppress_duplicates: true,
+            synchronous: cfg!(debug_assertions),
+        }
+    }
+}
+
+impl DebugOutput {
+    pub fn ignore(mut self, id: u32) -> Self {
+        self.ignored_ids.insert(id);
+        self
+    }
+
+    ///
+    /// Registers the debug callback, must be done before the context is shared
+    ///
+    pub fn install(&self, gl: &mut glow::Context) {
+        if !self.enabled || !gl.supports_debug() {
+            return;
+        }
+
+        let seen = Mutex::new(HashMap::<(u32, u32, u32), u64>::new());
+        let suppress_duplicates = self.suppress_duplicates;
+        let ignored_ids = self.ignored_ids.clone();
+
+        unsafe {
+            gl.enable(DEBUG_OUTPUT);
+            if self.synchronous {
+                gl.enable(DEBUG_OUTPUT_SYNCHRONOUS);
+            }
+
+            // Filtering ids through debug_message_control needs a concrete source and type,
+            // the ids ignored whatever their source are dropped here instead
+            gl.debug_message_callback(move |source, kind, id, severity, message| {
+                if ignored_ids.contains(&id) {
+                    return;
+                }
+
+                if suppress_duplicates {
+                    let mut seen = seen.lock().unwrap_or_else(|e| e.into_inner());
+                    let count = seen.entry((source, kind, id)).or_default();
+                    *count += 1;
+
+                    match *count {
+                        1 => {}
+                        count if is_power_of_ten(count) => {
+                            log!(
+                                severity_level(severity),
+                                "GL {} {} [{id}] repeated {count} times: {message}",
+                                source_name(source),
+                                type_name(kind),
+                            );
+                            return;
+                        }
+                        _ => return,
+                    }
+                }
+
+                log!(
+                    severity_level(severity),
+                    "GL {} {} [{id}]: {message}",
+                    source_name(source),
+                    type_name(kind),
+                );
+            });
+        }
+
+        info!("Installed GL debug output");
+    }
+}
+
+fn is_power_of_ten(mut value: u64) -> bool {
+    while value.is_multiple_of(10) {
+        value /= 10;
+    }
+    value == 1
+}
+
+fn severity_level(severity: u32) -> Level {
+    match severity {
+        DEBUG_SEVERITY_HIGH => Level::Error,
+        DEBUG_SEVERITY_MEDIUM => Level::Warn,
+        DEBUG_SEVERITY_LOW => Level::Info,
+        DEBUG_SEVERITY_NOTIFICATION => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+fn source_name(source: u32) -> &'static str {
+    match source {
+        DEBUG_SOURCE_API => "API",
+        DEBUG_SOURCE_WINDOW_SYSTEM => "window system",
+        DEBUG_SOURCE_SHADER_COMPILER => "shader compiler",
+        DEBUG_SOURCE_THIRD_PARTY => "third party",
+        DEBUG_SOURCE_APPLICATION => "application",
+        DEBUG_SOURCE_OTHER => "other",
+        _ => "unknown source",
+    }
+}
+
+fn type_name(kind: u32) -> &'static str {
+    match kind {
+        DEBUG_TYPE_ERROR => "error",
+        DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated behavior",
+        DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined behavior",
+        DEBUG_TYPE_PORTABILITY => "portability",
+        DEBUG_TYPE_PERFORMANCE => "performance",
+        DEBUG_TYPE_MARKER => "marker",
+        DEBUG_TYPE_PUSH_GROUP => "push group",
+        DEBUG_TYPE_POP_GROUP => "pop group",
+        DEBUG_TYPE_OTHER => "other",
+        _ => "unknown type",
+    }
+}
+
+///
+/// Names a GL object so that debug messages refer to it
+///
+pub fn set_label(gl: &GlState, identifier: u32, name: u32, label: &str) {
+    if !gl.context().supports_debug() {
+        return;
+    }
+
+    unsafe {
+        gl.context().object_label(identifier, name, Some(label));
+    }
+}
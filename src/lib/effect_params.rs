@@ -0,0 +1,143 @@
+use std::ops::RangeInclusive;
+
+use egui::{ComboBox, DragValue, Slider, Ui};
+pub use seagull_derive::EffectParams;
+
+///
+/// Description of a tunable given to its widget
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParamInfo {
+    /// Field name
+    pub name: &'static str,
+    /// Doc comment of the field
+    pub tooltip: &'static str,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Drag speed of values without both bounds
+    pub step: Option<f64>,
+}
+
+impl ParamInfo {
+    pub const DEFAULT: ParamInfo = ParamInfo {
+        name: "",
+        tooltip: "",
+        min: None,
+        max: None,
+        step: None,
+    };
+
+    ///
+    /// Field name made readable, `dither_spread` giving "Dither spread"
+    ///
+    pub fn label(&self) -> String {
+        let mut label = self.name.replace('_', " ");
+        if let Some(first) = label.get_mut(..1) {
+            first.make_ascii_uppercase();
+        }
+        label
+    }
+
+    fn range(&self) -> Option<RangeInclusive<f64>> {
+        Some(self.min?..=self.max?)
+    }
+}
+
+///
+/// Value that can be edited by a widget of the inspector
+///
+pub trait Param {
+    ///
+    /// Draws the widget, returns whether the value changed
+    ///
+    fn ui(&mut self, ui: &mut Ui, info: &ParamInfo) -> bool;
+}
+
+macro_rules! impl_numeric_param {
+    ($($type:ty),*) => {
+        $(
+            impl Param for $type {
+                fn ui(&mut self, ui: &mut Ui, info: &ParamInfo) -> bool {
+                    let response = match info.range() {
+                        Some(range) => ui.add(Slider::new(
+                            self,
+                            (*range.start() as $type)..=(*range.end() as $type),
+                        )),
+                        None => ui.add(
+                            DragValue::new(self)
+                                .speed(info.step.unwrap_or(0.1))
+                                .range(
+                                    info.min.unwrap_or(f64::NEG_INFINITY)
+                                        ..=info.max.unwrap_or(f64::INFINITY),
+                                ),
+                        ),
+                    };
+                    response.on_hover_text(info.tooltip.trim()).changed()
+                }
+            }
+        )*
+    };
+}
+
+impl_numeric_param!(f32, f64, i32, u32, usize);
+
+impl Param for bool {
+    fn ui(&mut self, ui: &mut Ui, info: &ParamInfo) -> bool {
+        ui.checkbox(self, "")
+            .on_hover_text(info.tooltip.trim())
+            .changed()
+    }
+}
+
+///
+/// Enum edited with a combo box listing every value
+///
+pub trait ParamChoice: Copy + PartialEq + 'static {
+    const ALL: &'static [Self];
+
+    fn name(self) -> &'static str;
+}
+
+impl<T: ParamChoice> Param for T {
+    fn ui(&mut self, ui: &mut Ui, info: &ParamInfo) -> bool {
+        let before = *self;
+        ComboBox::from_id_salt(info.name)
+            .selected_text(self.name())
+            .show_ui(ui, |ui| {
+                for &value in T::ALL {
+                    ui.selectable_value(self, value, value.name());
+                }
+            })
+            .response
+            .on_hover_text(info.tooltip.trim());
+        *self != before
+    }
+}
+
+///
+/// Tunables of an effect, implemented with `#[derive(EffectParams)]`
+///
+pub trait EffectParams: Default {
+    const PARAMS: &'static [ParamInfo];
+
+    ///
+    /// Draws a labelled widget per parameter as rows of a two column grid,
+    /// returns whether a value changed
+    ///
+    fn edit(&mut self, ui: &mut Ui) -> bool;
+
+    ///
+    /// Inspector of every parameter, with a button putting the defaults back
+    ///
+    fn ui(&mut self, ui: &mut Ui, id: &str) -> bool {
+        let mut changed = false;
+        egui::Grid::new(id)
+            .num_columns(2)
+            .show(ui, |ui| changed = self.edit(ui));
+        if ui.button("Reset").clicked() {
+            *self = Self::default();
+            changed = true;
+        }
+        changed
+    }
+}
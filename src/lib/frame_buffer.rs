@@ -1,113 +1,514 @@
-use std::sync::Arc;
+use std::rc::Rc;
 
 use anyhow::Result;
 use glow::{
-    COLOR_ATTACHMENT0, DEPTH_ATTACHMENT, DEPTH_COMPONENT24, FRAMEBUFFER, FRAMEBUFFER_COMPLETE,
-    FRAMEBUFFER_INCOMPLETE_ATTACHMENT, FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER,
+    COLOR_ATTACHMENT0, COLOR_BUFFER_BIT, DEPTH_ATTACHMENT, DEPTH_BUFFER_BIT, DEPTH_COMPONENT24,
+    DEPTH_STENCIL_ATTACHMENT, DEPTH24_STENCIL8, DEPTH32F_STENCIL8, FRAMEBUFFER,
+    FRAMEBUFFER_COMPLETE, FRAMEBUFFER_INCOMPLETE_ATTACHMENT, FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER,
     FRAMEBUFFER_INCOMPLETE_LAYER_TARGETS, FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT,
     FRAMEBUFFER_INCOMPLETE_MULTISAMPLE, FRAMEBUFFER_INCOMPLETE_READ_BUFFER, FRAMEBUFFER_UNDEFINED,
-    FRAMEBUFFER_UNSUPPORTED, HasContext, RGBA8,
+    FRAMEBUFFER_UNSUPPORTED, HasContext, NEAREST, PACK_ALIGNMENT, PixelPackData, RENDERBUFFER,
+    RGBA, RGBA8, STENCIL_ATTACHMENT, STENCIL_BUFFER_BIT, STENCIL_INDEX8, UNSIGNED_BYTE,
 };
 use log::info;
 
-use crate::texture::Texture;
+use crate::{
+    debug, fullscreen_pass::Viewport, gl_state::GlState, render_buffer::RenderBuffer,
+    texture::Texture,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AttachmentStorage {
+    /// Can be sampled by later passes
+    Texture,
+    /// Can only be rendered to, blitted or read back
+    RenderBuffer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AttachmentDesc {
+    pub format: u32,
+    pub storage: AttachmentStorage,
+}
+
+impl AttachmentDesc {
+    pub fn texture(format: u32) -> Self {
+        Self {
+            format,
+            storage: AttachmentStorage::Texture,
+        }
+    }
+
+    pub fn render_buffer(format: u32) -> Self {
+        Self {
+            format,
+            storage: AttachmentStorage::RenderBuffer,
+        }
+    }
+}
+
+///
+/// Describes the attachments of a frame buffer
+///
+/// Color attachments are numbered in the order they are added and are all enabled
+/// as draw buffers, so fragment output `location = n` writes to the `n`th one.
+///
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FrameBufferDesc {
+    pub width: i32,
+    pub height: i32,
+    /// Samples per pixel of every attachment, 0 or 1 for no multisampling
+    pub samples: i32,
+    pub color: Vec<AttachmentDesc>,
+    pub depth_stencil: Option<AttachmentDesc>,
+}
+
+impl FrameBufferDesc {
+    pub fn new(width: i32, height: i32) -> Self {
+        Self {
+            width,
+            height,
+            samples: 0,
+            color: Vec::new(),
+            depth_stencil: None,
+        }
+    }
+
+    ///
+    /// Multisamples all the attachments, the result is read by resolving it into another frame buffer
+    ///
+    pub fn samples(mut self, samples: i32) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn color(mut self, attachment: AttachmentDesc) -> Self {
+        self.color.push(attachment);
+        self
+    }
+
+    ///
+    /// Sets the depth, stencil or combined depth stencil attachment, depending on its format
+    ///
+    pub fn depth_stencil(mut self, attachment: AttachmentDesc) -> Self {
+        self.depth_stencil = Some(attachment);
+        self
+    }
+}
+
+pub enum Attachment {
+    Texture(Texture),
+    RenderBuffer(RenderBuffer),
+}
+
+impl Attachment {
+    fn new(
+        gl: Rc<GlState>,
+        width: i32,
+        height: i32,
+        samples: i32,
+        desc: AttachmentDesc,
+    ) -> Result<Self> {
+        Ok(match desc.storage {
+            AttachmentStorage::Texture => Attachment::Texture(Texture::multisample(
+                gl,
+                width,
+                height,
+                desc.format,
+                samples,
+            )?),
+            AttachmentStorage::RenderBuffer => Attachment::RenderBuffer(RenderBuffer::multisample(
+                gl,
+                width,
+                height,
+                desc.format,
+                samples,
+            )?),
+        })
+    }
+
+    pub fn texture(&self) -> Option<&Texture> {
+        match self {
+            Attachment::Texture(texture) => Some(texture),
+            Attachment::RenderBuffer(_) => None,
+        }
+    }
+
+    fn resize(&mut self, width: i32, height: i32) -> Result<()> {
+        match self {
+            Attachment::Texture(texture) => texture.resize(width, height),
+            Attachment::RenderBuffer(render_buffer) => render_buffer.resize(width, height),
+        }
+    }
+
+    fn set_label(&mut self, label: &str) {
+        match self {
+            Attachment::Texture(texture) => texture.set_label(label),
+            Attachment::RenderBuffer(render_buffer) => render_buffer.set_label(label),
+        }
+    }
+
+    fn attach(&self, gl: &GlState, framebuffer: glow::Framebuffer, point: u32) {
+        unsafe {
+            match self {
+                Attachment::Texture(texture) => {
+                    gl.context().named_framebuffer_texture(
+                        Some(framebuffer),
+                        point,
+                        Some(texture.id),
+                        0,
+                    );
+                }
+                Attachment::RenderBuffer(render_buffer) => {
+                    gl.context().named_framebuffer_renderbuffer(
+                        Some(framebuffer),
+                        point,
+                        RENDERBUFFER,
+                        Some(render_buffer.id),
+                    );
+                }
+            }
+        }
+    }
+}
+
+///
+/// Attachment point of a depth and/or stencil format
+///
+fn depth_stencil_point(format: u32) -> u32 {
+    match format {
+        DEPTH24_STENCIL8 | DEPTH32F_STENCIL8 => DEPTH_STENCIL_ATTACHMENT,
+        STENCIL_INDEX8 => STENCIL_ATTACHMENT,
+        // Every DEPTH_COMPONENT format
+        _ => DEPTH_ATTACHMENT,
+    }
+}
+
+///
+/// Blit mask of the buffers stored by a depth and/or stencil format
+///
+fn depth_stencil_mask(format: u32) -> u32 {
+    match depth_stencil_point(format) {
+        DEPTH_STENCIL_ATTACHMENT => DEPTH_BUFFER_BIT | STENCIL_BUFFER_BIT,
+        STENCIL_ATTACHMENT => STENCIL_BUFFER_BIT,
+        _ => DEPTH_BUFFER_BIT,
+    }
+}
 
 pub struct FrameBuffer {
-    id: glow::Framebuffer,
+    pub id: glow::Framebuffer,
     width: i32,
     height: i32,
-    pub texture: Texture,
-    depth_texture: Texture,
-    gl: Arc<glow::Context>,
+    desc: FrameBufferDesc,
+    color_attachments: Vec<Attachment>,
+    depth_stencil_attachment: Option<Attachment>,
+    gl: Rc<GlState>,
 }
 
 impl FrameBuffer {
-    pub fn new(gl: Arc<glow::Context>, width: i32, height: i32) -> Result<Self> {
+    ///
+    /// Frame buffer with one `RGBA8` color texture and a 24 bits depth texture
+    ///
+    pub fn new(gl: Rc<GlState>, width: i32, height: i32) -> Result<Self> {
+        let desc = FrameBufferDesc::new(width, height)
+            .color(AttachmentDesc::texture(RGBA8))
+            .depth_stencil(AttachmentDesc::texture(DEPTH_COMPONENT24));
+
+        Self::from_desc(gl, desc)
+    }
+
+    pub fn from_desc(gl: Rc<GlState>, desc: FrameBufferDesc) -> Result<Self> {
         let id;
         unsafe {
             id = gl
+                .context()
                 .create_named_framebuffer()
                 .or_else(|s| Err(anyhow::anyhow!(s)))?;
         }
 
-        let texture = Texture::new(gl.clone(), width, height, RGBA8)?;
-        let depth_texture = Texture::new(gl.clone(), width, height, DEPTH_COMPONENT24)?;
+        // Owned before the attachments are created so that Drop deletes it if one fails
+        let mut frame_buffer = Self {
+            id,
+            width: desc.width,
+            height: desc.height,
+            desc,
+            color_attachments: Vec::new(),
+            depth_stencil_attachment: None,
+            gl,
+        };
 
-        unsafe {
-            gl.named_framebuffer_texture(Some(id), COLOR_ATTACHMENT0, Some(texture.id), 0);
-            gl.named_framebuffer_texture(Some(id), DEPTH_ATTACHMENT, Some(depth_texture.id), 0);
+        let FrameBufferDesc {
+            width,
+            height,
+            samples,
+            ..
+        } = frame_buffer.desc;
+        for &color in &frame_buffer.desc.color {
+            let attachment =
+                Attachment::new(frame_buffer.gl.clone(), width, height, samples, color)?;
+            frame_buffer.color_attachments.push(attachment);
+        }
+        if let Some(depth_stencil) = frame_buffer.desc.depth_stencil {
+            frame_buffer.depth_stencil_attachment = Some(Attachment::new(
+                frame_buffer.gl.clone(),
+                width,
+                height,
+                samples,
+                depth_stencil,
+            )?);
+        }
+        frame_buffer.attach_all();
 
-            let status = gl.check_named_framebuffer_status(Some(id), FRAMEBUFFER);
+        frame_buffer.restore_draw_buffers();
+
+        unsafe {
+            let status = frame_buffer
+                .gl
+                .context()
+                .check_named_framebuffer_status(Some(id), FRAMEBUFFER);
 
             Self::print_frame_buffer_status(status);
             if status != FRAMEBUFFER_COMPLETE {
                 return Err(anyhow::anyhow!("FrameBuffer non complete"));
             }
-
-            gl.bind_framebuffer(FRAMEBUFFER, None);
         }
 
         info!("Initialized frame buffer {id:?}");
 
-        Ok(Self {
-            id,
-            width,
-            height,
-            texture,
-            depth_texture,
-            gl,
-        })
+        Ok(frame_buffer)
+    }
+
+    pub fn desc(&self) -> &FrameBufferDesc {
+        &self.desc
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn is_multisampled(&self) -> bool {
+        self.desc.samples > 1
+    }
+
+    ///
+    /// Color texture at `index`, `None` when out of range or stored in a render buffer
+    ///
+    pub fn texture(&self, index: usize) -> Option<&Texture> {
+        self.color_attachments
+            .get(index)
+            .and_then(Attachment::texture)
+    }
+
+    pub fn depth_stencil_texture(&self) -> Option<&Texture> {
+        self.depth_stencil_attachment
+            .as_ref()
+            .and_then(Attachment::texture)
     }
 
     ///
-    /// Resizes the textures bound to this frame buffer object
+    /// Resizes the attachments of this frame buffer object
     ///
     pub fn resize(&mut self, width: i32, height: i32) -> Result<()> {
         self.height = height;
         self.width = width;
+        self.desc.width = width;
+        self.desc.height = height;
+
+        for attachment in self
+            .color_attachments
+            .iter_mut()
+            .chain(self.depth_stencil_attachment.as_mut())
+        {
+            attachment.resize(width, height)?;
+        }
+
+        self.attach_all();
 
-        self.texture.resize(width, height)?;
-        self.depth_texture.resize(width, height)?;
+        Ok(())
+    }
 
+    fn attach_all(&self) {
+        for (i, attachment) in self.color_attachments.iter().enumerate() {
+            attachment.attach(&self.gl, self.id, COLOR_ATTACHMENT0 + i as u32);
+        }
+
+        if let (Some(attachment), Some(desc)) =
+            (&self.depth_stencil_attachment, &self.desc.depth_stencil)
+        {
+            attachment.attach(&self.gl, self.id, depth_stencil_point(desc.format));
+        }
+    }
+
+    ///
+    /// Viewport covering the whole frame buffer
+    ///
+    pub fn viewport(&self) -> Viewport {
+        Viewport::new(0, 0, self.width, self.height)
+    }
+
+    ///
+    /// Copies a region of this frame buffer into `target`, or the default frame buffer when `None`
+    ///
+    /// `mask` selects the `COLOR_BUFFER_BIT`, `DEPTH_BUFFER_BIT` and `STENCIL_BUFFER_BIT` to copy,
+    /// `filter` is `NEAREST` or `LINEAR` and only applies to color when the regions differ in size.
+    /// Color is read from the first attachment and written to every draw buffer of `target`.
+    ///
+    pub fn blit(
+        &self,
+        target: Option<&FrameBuffer>,
+        source: Viewport,
+        destination: Viewport,
+        mask: u32,
+        filter: u32,
+    ) {
         unsafe {
-            self.gl.named_framebuffer_texture(
+            self.gl.context().blit_named_framebuffer(
                 Some(self.id),
-                COLOR_ATTACHMENT0,
-                Some(self.texture.id),
-                0,
-            );
-            self.gl.named_framebuffer_texture(
-                Some(self.id),
-                DEPTH_ATTACHMENT,
-                Some(self.depth_texture.id),
-                0,
+                target.map(|target| target.id),
+                source.x,
+                source.y,
+                source.x + source.width,
+                source.y + source.height,
+                destination.x,
+                destination.y,
+                destination.x + destination.width,
+                destination.y + destination.height,
+                mask,
+                filter,
             );
         }
+    }
+
+    ///
+    /// Resolves the samples of every attachment into the matching attachment of `target`
+    ///
+    /// Both frame buffers must be the same size, `target` usually being the single sampled
+    /// version of this one so its textures can be sampled by later passes.
+    ///
+    pub fn resolve_to(&self, target: &FrameBuffer) -> Result<()> {
+        if self.width != target.width || self.height != target.height {
+            return Err(anyhow::anyhow!(
+                "Cannot resolve {}x{} frame buffer {:?} into {}x{} frame buffer {:?}",
+                self.width,
+                self.height,
+                self.id,
+                target.width,
+                target.height,
+                target.id
+            ));
+        }
+
+        let viewport = self.viewport();
+        let colors = self
+            .color_attachments
+            .len()
+            .min(target.color_attachments.len()) as u32;
+
+        unsafe {
+            for i in 0..colors {
+                self.gl
+                    .context()
+                    .named_framebuffer_read_buffer(Some(self.id), COLOR_ATTACHMENT0 + i);
+                self.gl
+                    .context()
+                    .named_framebuffer_draw_buffer(Some(target.id), COLOR_ATTACHMENT0 + i);
+                self.blit(Some(target), viewport, viewport, COLOR_BUFFER_BIT, NEAREST);
+            }
+
+            self.gl
+                .context()
+                .named_framebuffer_read_buffer(Some(self.id), COLOR_ATTACHMENT0);
+            target.restore_draw_buffers();
+        }
+
+        if let (Some(source), Some(destination)) =
+            (&self.desc.depth_stencil, &target.desc.depth_stencil)
+        {
+            let mask = depth_stencil_mask(source.format) & depth_stencil_mask(destination.format);
+            self.blit(Some(target), viewport, viewport, mask, NEAREST);
+        }
 
         Ok(())
     }
 
     ///
-    /// Binds this frame buffer to be the current rendering target
+    /// Reads the first color attachment back as `RGBA8`, rows going from the bottom to the top
     ///
-    pub fn bind(&self) {
+    /// Multisampled frame buffers must be resolved first.
+    ///
+    pub fn read_pixels(&self) -> Result<Vec<u8>> {
+        if self.is_multisampled() {
+            return Err(anyhow::anyhow!(
+                "Cannot read the pixels of multisampled frame buffer {:?}",
+                self.id
+            ));
+        }
+
+        let mut pixels = vec![0; self.width as usize * self.height as usize * 4];
+        self.gl.set_framebuffer(Some(self.id));
         unsafe {
-            self.gl.viewport(0, 0, self.width, self.height);
-            self.gl.bind_framebuffer(FRAMEBUFFER, Some(self.id));
+            self.gl.context().pixel_store_i32(PACK_ALIGNMENT, 1);
+            self.gl.context().read_pixels(
+                0,
+                0,
+                self.width,
+                self.height,
+                RGBA,
+                UNSIGNED_BYTE,
+                PixelPackData::Slice(Some(&mut pixels)),
+            );
         }
+
+        Ok(pixels)
     }
 
     ///
-    /// Unbinds this frame buffer to stop being the current rendering target
+    /// Enables every color attachment as a draw buffer
     ///
-    pub fn unbind(&self, x: i32, y: i32, width: i32, height: i32) {
+    fn restore_draw_buffers(&self) {
+        let draw_buffers: Vec<u32> = (0..self.color_attachments.len() as u32)
+            .map(|i| COLOR_ATTACHMENT0 + i)
+            .collect();
         unsafe {
             self.gl
-                .viewport(x, y, width, height);
-            self.gl.bind_framebuffer(FRAMEBUFFER, None);
+                .context()
+                .named_framebuffer_draw_buffers(Some(self.id), &draw_buffers);
+        }
+    }
+
+    ///
+    /// Names this frame buffer and its textures in GL debug messages
+    ///
+    pub fn set_label(&mut self, label: &str) {
+        debug::set_label(&self.gl, FRAMEBUFFER, self.id.0.get(), label);
+        for (i, attachment) in self.color_attachments.iter_mut().enumerate() {
+            attachment.set_label(&format!("{label} color {i}"));
+        }
+        if let Some(attachment) = &mut self.depth_stencil_attachment {
+            attachment.set_label(&format!("{label} depth stencil"));
         }
     }
 
+    ///
+    /// Binds this frame buffer to be the current rendering target
+    ///
+    pub fn bind(&self) {
+        self.gl.set_viewport(self.viewport());
+        self.gl.set_framebuffer(Some(self.id));
+    }
+
+    ///
+    /// Unbinds this frame buffer to stop being the current rendering target
+    ///
+    pub fn unbind(&self, x: i32, y: i32, width: i32, height: i32) {
+        self.gl.set_viewport(Viewport::new(x, y, width, height));
+        self.gl.set_framebuffer(None);
+    }
+
     fn print_frame_buffer_status(status: u32) {
         match status {
             FRAMEBUFFER_COMPLETE => info!("Framebuffer complete"),
@@ -127,7 +528,8 @@ impl FrameBuffer {
 impl Drop for FrameBuffer {
     fn drop(&mut self) {
         unsafe {
-            self.gl.delete_framebuffer(self.id);
+            self.gl.forget_framebuffer(self.id);
+            self.gl.context().delete_framebuffer(self.id);
         }
     }
 }
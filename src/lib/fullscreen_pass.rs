@@ -0,0 +1,108 @@
+use std::rc::Rc;
+
+use anyhow::Result;
+use glow::{HasContext, TRIANGLES};
+use log::info;
+
+use crate::{
+    debug,
+    frame_buffer::FrameBuffer,
+    gl_state::GlState,
+    shader::{Shader, ShaderProgramBuilder, ShaderStage},
+};
+
+/// Vertex shader of every fullscreen pass, it outputs `vPos` from (0, 0) to (1, 1)
+pub const VERTEX_SHADER: &str = include_str!("Fullscreen-VS.glsl");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Viewport {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+///
+/// Runs fragment shaders over a whole render target
+///
+/// Owns the empty vertex array used to draw a single triangle generated from
+/// `gl_VertexID`, one instance is meant to be shared by every effect.
+///
+pub struct FullscreenPass {
+    vertex_array: glow::VertexArray,
+    gl: Rc<GlState>,
+}
+
+impl FullscreenPass {
+    pub fn new(gl: Rc<GlState>) -> Result<Self> {
+        let vertex_array;
+        unsafe {
+            vertex_array = gl
+                .context()
+                .create_named_vertex_array()
+                .or_else(|s| Err(anyhow::anyhow!(s)))?;
+        }
+        debug::set_label(
+            &gl,
+            glow::VERTEX_ARRAY,
+            vertex_array.0.get(),
+            "fullscreen triangle",
+        );
+
+        info!("Initialized fullscreen pass");
+
+        Ok(Self { vertex_array, gl })
+    }
+
+    ///
+    /// Links a fragment shader with the fullscreen vertex shader
+    ///
+    pub fn shader(&self, fragment_source: &str) -> Result<Shader> {
+        self.shader_builder(fragment_source).build()
+    }
+
+    ///
+    /// Builder of `shader`, to add defines or to make variants
+    ///
+    pub fn shader_builder(&self, fragment_source: &str) -> ShaderProgramBuilder {
+        ShaderProgramBuilder::new(self.gl.clone())
+            .stage(ShaderStage::Vertex, VERTEX_SHADER)
+            .stage(ShaderStage::Fragment, fragment_source)
+    }
+
+    ///
+    /// Draws `shader` over the viewport of `target`, or of the screen when there is none
+    ///
+    /// Uniforms and textures must be set on the shader beforehand.
+    ///
+    pub fn draw(&self, shader: &Shader, target: Option<&FrameBuffer>, viewport: Viewport) {
+        self.gl.set_framebuffer(target.map(|target| target.id));
+        self.gl.set_viewport(viewport);
+        shader.use_program();
+        self.gl.set_vertex_array(Some(self.vertex_array));
+
+        unsafe {
+            self.gl.context().draw_arrays(TRIANGLES, 0, 3);
+        }
+    }
+}
+
+impl Drop for FullscreenPass {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.forget_vertex_array(self.vertex_array);
+            self.gl.context().delete_vertex_array(self.vertex_array);
+        }
+    }
+}
@@ -0,0 +1,533 @@
+use std::{
+    cell::{OnceCell, RefCell},
+    collections::HashMap,
+    ffi::{CStr, c_void},
+    num::NonZeroU32,
+    sync::Arc,
+};
+
+use anyhow::Result;
+use glow::{
+    BLEND, DEPTH_TEST, FRAMEBUFFER, FUNC_ADD, HasContext, LESS, ONE, SCISSOR_TEST, TEXTURE0, ZERO,
+};
+use log::{info, warn};
+
+use crate::{
+    fullscreen_pass::Viewport, program_cache::ProgramCache, program_pipeline::PipelineFns,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlendState {
+    pub equation: u32,
+    pub src_rgb: u32,
+    pub dst_rgb: u32,
+    pub src_alpha: u32,
+    pub dst_alpha: u32,
+}
+
+impl BlendState {
+    ///
+    /// Source over destination, for colors that are not premultiplied
+    ///
+    pub fn alpha() -> Self {
+        Self {
+            equation: FUNC_ADD,
+            src_rgb: glow::SRC_ALPHA,
+            dst_rgb: glow::ONE_MINUS_SRC_ALPHA,
+            src_alpha: ONE,
+            dst_alpha: glow::ONE_MINUS_SRC_ALPHA,
+        }
+    }
+
+    pub fn additive() -> Self {
+        Self {
+            equation: FUNC_ADD,
+            src_rgb: ONE,
+            dst_rgb: ONE,
+            src_alpha: ONE,
+            dst_alpha: ONE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DepthState {
+    pub test: bool,
+    pub write: bool,
+    pub func: u32,
+}
+
+impl Default for DepthState {
+    fn default() -> Self {
+        Self {
+            test: false,
+            write: true,
+            func: LESS,
+        }
+    }
+}
+
+///
+/// Number of state changes sent to GL and skipped because they were already set
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StateStats {
+    pub issued: u64,
+    pub skipped: u64,
+}
+
+///
+/// Last values given to GL, `None` when unknown
+///
+#[derive(Clone, Default)]
+struct Shadow {
+    program: Option<Option<glow::Program>>,
+    program_pipeline: Option<Option<NonZeroU32>>,
+    vertex_array: Option<Option<glow::VertexArray>>,
+    framebuffer: Option<Option<glow::Framebuffer>>,
+    active_texture: Option<u32>,
+    /// Bound texture by unit and target
+    textures: HashMap<(u32, u32), Option<glow::Texture>>,
+    viewport: Option<Viewport>,
+    blend: Option<Option<BlendState>>,
+    depth: Option<DepthState>,
+    scissor: Option<Option<Viewport>>,
+}
+
+impl Shadow {
+    ///
+    /// State of a freshly created context, before the default viewport is known
+    ///
+    fn initial() -> Self {
+        Self {
+            program: Some(None),
+            program_pipeline: Some(None),
+            vertex_array: Some(None),
+            framebuffer: Some(None),
+            active_texture: Some(TEXTURE0),
+            textures: HashMap::new(),
+            viewport: None,
+            blend: Some(None),
+            depth: Some(DepthState::default()),
+            scissor: Some(None),
+        }
+    }
+}
+
+struct State {
+    shadow: Shadow,
+    frame: StateStats,
+    last_frame: StateStats,
+    total: StateStats,
+}
+
+///
+/// GL context that remembers the bound objects and fixed function state
+///
+/// Wrappers set state through it so that binding what is already bound is skipped.
+/// Every other call goes through the raw context returned by `context`. State changed
+/// that way is invisible to the cache, so code outside the engine (like the egui
+/// painter) must run inside `scope` which puts the cached state back afterwards.
+///
+pub struct GlState {
+    state: RefCell<State>,
+    context: Arc<glow::Context>,
+    pipeline_fns: Option<PipelineFns>,
+    /// `glGetProgramiv`, which glow only exposes for parameters holding one value
+    get_programiv: Option<unsafe extern "system" fn(u32, u32, *mut i32)>,
+    program_cache: OnceCell<ProgramCache>,
+}
+
+impl GlState {
+    ///
+    /// Wraps a context that has not been used yet, its state is assumed to be the GL defaults
+    ///
+    /// `loader` is the one the context was created with, it loads the entry points
+    /// glow lacks.
+    ///
+    pub fn new(context: glow::Context, loader: impl Fn(&CStr) -> *const c_void) -> Self {
+        let pipeline_fns = PipelineFns::load(&loader);
+        if pipeline_fns.is_none() {
+            warn!("Program pipelines are not supported by the driver");
+        }
+        let get_programiv = loader(c"glGetProgramiv");
+        let get_programiv = (!get_programiv.is_null()).then(|| {
+            // SAFETY: the GL specification gives the signature of glGetProgramiv
+            unsafe {
+                std::mem::transmute::<*const c_void, unsafe extern "system" fn(u32, u32, *mut i32)>(
+                    get_programiv,
+                )
+            }
+        });
+
+        info!("Initialized GL state cache");
+
+        Self {
+            state: RefCell::new(State {
+                shadow: Shadow::initial(),
+                frame: StateStats::default(),
+                last_frame: StateStats::default(),
+                total: StateStats::default(),
+            }),
+            context: Arc::new(context),
+            pipeline_fns,
+            get_programiv,
+            program_cache: OnceCell::new(),
+        }
+    }
+
+    ///
+    /// Raw context, for the calls the cache does not track and for libraries that take
+    /// their own `glow::Context`
+    ///
+    pub fn context(&self) -> &Arc<glow::Context> {
+        &self.context
+    }
+
+    pub fn set_program(&self, program: Option<glow::Program>) {
+        if self.changed(|shadow| &mut shadow.program, program) {
+            unsafe { self.context.use_program(program) };
+        }
+    }
+
+    ///
+    /// Binds a program pipeline, only used while no program is set with `set_program`
+    ///
+    pub fn set_program_pipeline(&self, pipeline: Option<NonZeroU32>) {
+        if self.changed(|shadow| &mut shadow.program_pipeline, pipeline)
+            && let Some(fns) = &self.pipeline_fns
+        {
+            unsafe { fns.bind_program_pipeline(pipeline) };
+        }
+    }
+
+    pub fn set_vertex_array(&self, vertex_array: Option<glow::VertexArray>) {
+        if self.changed(|shadow| &mut shadow.vertex_array, vertex_array) {
+            unsafe { self.context.bind_vertex_array(vertex_array) };
+        }
+    }
+
+    ///
+    /// Binds a frame buffer for both drawing and reading, `None` being the default one
+    ///
+    pub fn set_framebuffer(&self, framebuffer: Option<glow::Framebuffer>) {
+        if self.changed(|shadow| &mut shadow.framebuffer, framebuffer) {
+            unsafe { self.context.bind_framebuffer(FRAMEBUFFER, framebuffer) };
+        }
+    }
+
+    pub fn set_viewport(&self, viewport: Viewport) {
+        if self.changed(|shadow| &mut shadow.viewport, viewport) {
+            unsafe {
+                self.context
+                    .viewport(viewport.x, viewport.y, viewport.width, viewport.height)
+            };
+        }
+    }
+
+    ///
+    /// Binds `texture` to `target` of `unit`, `unit` going from `TEXTURE0` up
+    ///
+    pub fn set_texture(&self, unit: u32, target: u32, texture: Option<glow::Texture>) {
+        if self.changed(|shadow| &mut shadow.active_texture, unit) {
+            unsafe { self.context.active_texture(unit) };
+        }
+
+        let bound = self.state.borrow().shadow.textures.get(&(unit, target)) == Some(&texture);
+        if bound {
+            self.count(false);
+        } else {
+            self.state
+                .borrow_mut()
+                .shadow
+                .textures
+                .insert((unit, target), texture);
+            self.count(true);
+            unsafe { self.context.bind_texture(target, texture) };
+        }
+    }
+
+    ///
+    /// Enables blending with the given equation and factors, or disables it with `None`
+    ///
+    pub fn set_blend(&self, blend: Option<BlendState>) {
+        if self.changed(|shadow| &mut shadow.blend, blend) {
+            unsafe { self.apply_blend(blend) };
+        }
+    }
+
+    pub fn set_depth(&self, depth: DepthState) {
+        if self.changed(|shadow| &mut shadow.depth, depth) {
+            unsafe { self.apply_depth(depth) };
+        }
+    }
+
+    ///
+    /// Restricts drawing to `rect`, or lifts the restriction with `None`
+    ///
+    pub fn set_scissor(&self, rect: Option<Viewport>) {
+        if self.changed(|shadow| &mut shadow.scissor, rect) {
+            unsafe { self.apply_scissor(rect) };
+        }
+    }
+
+    ///
+    /// Runs code that changes GL state behind the cache, then restores the cached state
+    ///
+    pub fn scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        let result = f();
+        self.restore();
+        result
+    }
+
+    ///
+    /// Sends every known cached value to GL again
+    ///
+    pub fn restore(&self) {
+        let shadow = self.state.borrow().shadow.clone();
+
+        unsafe {
+            if let Some(program) = shadow.program {
+                self.context.use_program(program);
+            }
+            if let (Some(pipeline), Some(fns)) = (shadow.program_pipeline, &self.pipeline_fns) {
+                fns.bind_program_pipeline(pipeline);
+            }
+            if let Some(vertex_array) = shadow.vertex_array {
+                self.context.bind_vertex_array(vertex_array);
+            }
+            if let Some(framebuffer) = shadow.framebuffer {
+                self.context.bind_framebuffer(FRAMEBUFFER, framebuffer);
+            }
+            if let Some(viewport) = shadow.viewport {
+                self.context
+                    .viewport(viewport.x, viewport.y, viewport.width, viewport.height);
+            }
+            for (&(unit, target), &texture) in &shadow.textures {
+                self.context.active_texture(unit);
+                self.context.bind_texture(target, texture);
+            }
+            if let Some(unit) = shadow.active_texture {
+                self.context.active_texture(unit);
+            }
+            if let Some(blend) = shadow.blend {
+                self.apply_blend(blend);
+            }
+            if let Some(depth) = shadow.depth {
+                self.apply_depth(depth);
+            }
+            if let Some(scissor) = shadow.scissor {
+                self.apply_scissor(scissor);
+            }
+        }
+    }
+
+    ///
+    /// Forgets the whole cache, the next change of every state is sent to GL
+    ///
+    pub fn invalidate(&self) {
+        self.state.borrow_mut().shadow = Shadow::default();
+    }
+
+    ///
+    /// Must be called when deleting a program, GL reuses the names of deleted objects
+    ///
+    pub fn forget_program(&self, program: glow::Program) {
+        let mut state = self.state.borrow_mut();
+        if state.shadow.program == Some(Some(program)) {
+            state.shadow.program = None;
+        }
+    }
+
+    pub fn forget_program_pipeline(&self, pipeline: NonZeroU32) {
+        let mut state = self.state.borrow_mut();
+        if state.shadow.program_pipeline == Some(Some(pipeline)) {
+            state.shadow.program_pipeline = None;
+        }
+    }
+
+    pub fn forget_vertex_array(&self, vertex_array: glow::VertexArray) {
+        let mut state = self.state.borrow_mut();
+        if state.shadow.vertex_array == Some(Some(vertex_array)) {
+            state.shadow.vertex_array = None;
+        }
+    }
+
+    pub fn forget_framebuffer(&self, framebuffer: glow::Framebuffer) {
+        let mut state = self.state.borrow_mut();
+        if state.shadow.framebuffer == Some(Some(framebuffer)) {
+            state.shadow.framebuffer = None;
+        }
+    }
+
+    pub fn forget_texture(&self, texture: glow::Texture) {
+        self.state
+            .borrow_mut()
+            .shadow
+            .textures
+            .retain(|_, bound| *bound != Some(texture));
+    }
+
+    ///
+    /// Separable program and pipeline entry points, missing on drivers before GL 4.1
+    ///
+    pub(crate) fn pipeline_fns(&self) -> Result<&PipelineFns> {
+        self.pipeline_fns
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Program pipelines are not supported by the driver"))
+    }
+
+    ///
+    /// `local_size_x`, `local_size_y` and `local_size_z` of a linked compute program
+    ///
+    /// Read from the program rather than its source, so sizes given by macros are resolved.
+    ///
+    pub(crate) fn compute_work_group_size(&self, program: glow::Program) -> Result<[u32; 3]> {
+        let get_programiv = self
+            .get_programiv
+            .ok_or_else(|| anyhow::anyhow!("glGetProgramiv is not exposed by the driver"))?;
+        let mut size = [0; 3];
+        unsafe {
+            get_programiv(
+                program.0.get(),
+                glow::COMPUTE_WORK_GROUP_SIZE,
+                size.as_mut_ptr(),
+            )
+        };
+        Ok(size.map(|size: i32| size as u32))
+    }
+
+    ///
+    /// Makes every program built afterwards go through `cache`, only the first cache is kept
+    ///
+    pub fn set_program_cache(&self, cache: ProgramCache) {
+        if self.program_cache.set(cache).is_err() {
+            warn!("A program cache is already set");
+        }
+    }
+
+    pub fn program_cache(&self) -> Option<&ProgramCache> {
+        self.program_cache.get()
+    }
+
+    ///
+    /// Closes the counters of the frame
+    ///
+    pub fn end_frame(&self) {
+        let mut state = self.state.borrow_mut();
+        state.last_frame = std::mem::take(&mut state.frame);
+    }
+
+    ///
+    /// Counters of the last complete frame
+    ///
+    pub fn frame_stats(&self) -> StateStats {
+        self.state.borrow().last_frame
+    }
+
+    pub fn total_stats(&self) -> StateStats {
+        self.state.borrow().total
+    }
+
+    ///
+    /// Draws the state change counters
+    ///
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        let frame = self.frame_stats();
+        let total = self.total_stats();
+        egui::Grid::new("GlState").num_columns(3).show(ui, |ui| {
+            ui.label("");
+            ui.label("Frame");
+            ui.label("Total");
+            ui.end_row();
+            ui.label("Issued");
+            ui.label(frame.issued.to_string());
+            ui.label(total.issued.to_string());
+            ui.end_row();
+            ui.label("Skipped");
+            ui.label(frame.skipped.to_string());
+            ui.label(total.skipped.to_string());
+            ui.end_row();
+        });
+    }
+
+    ///
+    /// Stores `value` in the cache, returns whether it differs from the cached one
+    ///
+    fn changed<T: PartialEq>(
+        &self,
+        field: impl FnOnce(&mut Shadow) -> &mut Option<T>,
+        value: T,
+    ) -> bool {
+        let changed = {
+            let mut state = self.state.borrow_mut();
+            let cached = field(&mut state.shadow);
+            if cached.as_ref() == Some(&value) {
+                false
+            } else {
+                *cached = Some(value);
+                true
+            }
+        };
+        self.count(changed);
+        changed
+    }
+
+    fn count(&self, issued: bool) {
+        let mut state = self.state.borrow_mut();
+        if issued {
+            state.frame.issued += 1;
+            state.total.issued += 1;
+        } else {
+            state.frame.skipped += 1;
+            state.total.skipped += 1;
+        }
+    }
+
+    unsafe fn apply_blend(&self, blend: Option<BlendState>) {
+        unsafe {
+            match blend {
+                Some(blend) => {
+                    self.context.enable(BLEND);
+                    self.context.blend_equation(blend.equation);
+                    self.context.blend_func_separate(
+                        blend.src_rgb,
+                        blend.dst_rgb,
+                        blend.src_alpha,
+                        blend.dst_alpha,
+                    );
+                }
+                None => {
+                    self.context.disable(BLEND);
+                    self.context.blend_equation(FUNC_ADD);
+                    self.context.blend_func_separate(ONE, ZERO, ONE, ZERO);
+                }
+            }
+        }
+    }
+
+    unsafe fn apply_depth(&self, depth: DepthState) {
+        unsafe {
+            if depth.test {
+                self.context.enable(DEPTH_TEST);
+            } else {
+                self.context.disable(DEPTH_TEST);
+            }
+            self.context.depth_mask(depth.write);
+            self.context.depth_func(depth.func);
+        }
+    }
+
+    unsafe fn apply_scissor(&self, rect: Option<Viewport>) {
+        unsafe {
+            match rect {
+                Some(rect) => {
+                    self.context.enable(SCISSOR_TEST);
+                    self.context
+                        .scissor(rect.x, rect.y, rect.width, rect.height);
+                }
+                None => self.context.disable(SCISSOR_TEST),
+            }
+        }
+    }
+}
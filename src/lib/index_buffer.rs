@@ -0,0 +1,94 @@
+use std::rc::Rc;
+
+use anyhow::Result;
+use bytemuck::{Pod, cast_slice};
+use glow::{HasContext, STATIC_DRAW, UNSIGNED_BYTE, UNSIGNED_INT, UNSIGNED_SHORT};
+use log::info;
+
+use crate::{debug, gl_state::GlState};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexType {
+    U8,
+    U16,
+    U32,
+}
+
+impl IndexType {
+    pub fn gl_type(self) -> u32 {
+        match self {
+            IndexType::U8 => UNSIGNED_BYTE,
+            IndexType::U16 => UNSIGNED_SHORT,
+            IndexType::U32 => UNSIGNED_INT,
+        }
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            IndexType::U8 => 1,
+            IndexType::U16 => 2,
+            IndexType::U32 => 4,
+        }
+    }
+}
+
+///
+/// Integer types usable as vertex indices
+///
+pub trait Index: Pod {
+    const TYPE: IndexType;
+}
+
+impl Index for u8 {
+    const TYPE: IndexType = IndexType::U8;
+}
+
+impl Index for u16 {
+    const TYPE: IndexType = IndexType::U16;
+}
+
+impl Index for u32 {
+    const TYPE: IndexType = IndexType::U32;
+}
+
+pub struct IndexBuffer {
+    pub id: glow::Buffer,
+    pub index_type: IndexType,
+    pub index_count: i32,
+    gl: Rc<GlState>,
+}
+
+impl IndexBuffer {
+    pub fn new<I: Index>(gl: Rc<GlState>, indices: &[I]) -> Result<Self> {
+        let id;
+
+        unsafe {
+            id = gl.context().create_named_buffer().or_else(|s| Err(anyhow::anyhow!(s)))?;
+            gl.context().named_buffer_data_u8_slice(id, cast_slice(indices), STATIC_DRAW);
+        }
+
+        info!("Initialized index buffer {id:?}");
+
+        Ok(Self {
+            id,
+            index_type: I::TYPE,
+            index_count: indices.len() as i32,
+            gl,
+        })
+    }
+
+    ///
+    /// Names this buffer in GL debug messages
+    ///
+    pub fn set_label(&self, label: &str) {
+        debug::set_label(&self.gl, glow::BUFFER, self.id.0.get(), label);
+    }
+}
+
+impl Drop for IndexBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.context().delete_buffer(self.id);
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::{collections::HashMap, collections::HashSet, fs, path::Path};
+
+use anyhow::{Context, Result};
+use glm::{Vec2, vec2};
+use log::info;
+use serde::{Deserialize, Serialize};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
+};
+
+/// Number of pixels counted as one line when a device reports pixel scroll deltas
+const PIXELS_PER_LINE: f32 = 20.0;
+
+///
+/// Tracks keyboard and mouse state between frames
+///
+/// Pressed and released sets only hold transitions that happened since the last frame.
+///
+pub struct Input {
+    keys_held: HashSet<KeyCode>,
+    keys_pressed: HashSet<KeyCode>,
+    keys_released: HashSet<KeyCode>,
+    buttons_held: HashSet<MouseButton>,
+    buttons_pressed: HashSet<MouseButton>,
+    buttons_released: HashSet<MouseButton>,
+    cursor_position: Option<PhysicalPosition<f64>>,
+    scroll_delta: Vec2,
+    modifiers: ModifiersState,
+    size: PhysicalSize<u32>,
+    pub actions: ActionMap,
+}
+
+impl Input {
+    pub fn new(size: PhysicalSize<u32>, actions: ActionMap) -> Self {
+        Self {
+            keys_held: HashSet::new(),
+            keys_pressed: HashSet::new(),
+            keys_released: HashSet::new(),
+            buttons_held: HashSet::new(),
+            buttons_pressed: HashSet::new(),
+            buttons_released: HashSet::new(),
+            cursor_position: None,
+            scroll_delta: vec2(0.0, 0.0),
+            modifiers: ModifiersState::empty(),
+            size,
+            actions,
+        }
+    }
+
+    ///
+    /// Updates the state from a window event
+    ///
+    /// Presses and scrolling consumed by the UI are ignored, but releases are always
+    /// recorded so that nothing stays held once the UI lets go of the input.
+    ///
+    pub fn handle_event(&mut self, event: &WindowEvent, consumed: bool) {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } => {
+                let PhysicalKey::Code(code) = event.physical_key else {
+                    return;
+                };
+
+                match event.state {
+                    ElementState::Pressed if !consumed && !event.repeat => {
+                        self.keys_held.insert(code);
+                        self.keys_pressed.insert(code);
+                    }
+                    ElementState::Released if self.keys_held.remove(&code) => {
+                        self.keys_released.insert(code);
+                    }
+                    _ => {}
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed if !consumed => {
+                    self.buttons_held.insert(*button);
+                    self.buttons_pressed.insert(*button);
+                }
+                ElementState::Released if self.buttons_held.remove(button) => {
+                    self.buttons_released.insert(*button);
+                }
+                _ => {}
+            },
+            WindowEvent::MouseWheel { delta, .. } if !consumed => {
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => vec2(*x, *y),
+                    MouseScrollDelta::PixelDelta(position) => vec2(
+                        position.x as f32 / PIXELS_PER_LINE,
+                        position.y as f32 / PIXELS_PER_LINE,
+                    ),
+                };
+                self.scroll_delta = vec2(
+                    self.scroll_delta.x + delta.x,
+                    self.scroll_delta.y + delta.y,
+                );
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = Some(*position);
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor_position = None;
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+            }
+            WindowEvent::Focused(false) => {
+                self.release_all();
+            }
+            WindowEvent::Resized(size) => {
+                self.size = *size;
+            }
+            _ => {}
+        }
+    }
+
+    ///
+    /// Clears the per frame transitions, called by the runtime after each frame
+    ///
+    pub fn end_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.buttons_pressed.clear();
+        self.buttons_released.clear();
+        self.scroll_delta = vec2(0.0, 0.0);
+    }
+
+    fn release_all(&mut self) {
+        self.keys_released.extend(self.keys_held.drain());
+        self.buttons_released.extend(self.buttons_held.drain());
+    }
+
+    pub fn is_key_held(&self, key: KeyCode) -> bool {
+        self.keys_held.contains(&key)
+    }
+
+    pub fn is_key_pressed(&self, key: KeyCode) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn is_key_released(&self, key: KeyCode) -> bool {
+        self.keys_released.contains(&key)
+    }
+
+    pub fn is_button_held(&self, button: MouseButton) -> bool {
+        self.buttons_held.contains(&button)
+    }
+
+    pub fn is_button_pressed(&self, button: MouseButton) -> bool {
+        self.buttons_pressed.contains(&button)
+    }
+
+    pub fn is_button_released(&self, button: MouseButton) -> bool {
+        self.buttons_released.contains(&button)
+    }
+
+    ///
+    /// Cursor position in window pixels, with the origin at the top left
+    ///
+    pub fn cursor_position(&self) -> Option<Vec2> {
+        self.cursor_position
+            .map(|position| vec2(position.x as f32, position.y as f32))
+    }
+
+    ///
+    /// Cursor position in the same space as the `vPos` shader varying,
+    /// from (0, 0) at the bottom left to (1, 1) at the top right
+    ///
+    pub fn cursor_vpos(&self) -> Option<Vec2> {
+        if self.size.width == 0 || self.size.height == 0 {
+            return None;
+        }
+
+        self.cursor_position.map(|position| {
+            vec2(
+                position.x as f32 / self.size.width as f32,
+                1.0 - position.y as f32 / self.size.height as f32,
+            )
+        })
+    }
+
+    ///
+    /// Scroll accumulated since the last frame, in lines
+    ///
+    pub fn scroll_delta(&self) -> Vec2 {
+        self.scroll_delta
+    }
+
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    pub fn is_action_held(&self, action: &str) -> bool {
+        self.actions
+            .bindings(action)
+            .any(|binding| match binding {
+                Binding::Key(key) => self.is_key_held(*key),
+                Binding::Mouse(button) => self.is_button_held(*button),
+            })
+    }
+
+    pub fn is_action_pressed(&self, action: &str) -> bool {
+        self.actions
+            .bindings(action)
+            .any(|binding| match binding {
+                Binding::Key(key) => self.is_key_pressed(*key),
+                Binding::Mouse(button) => self.is_button_pressed(*button),
+            })
+    }
+
+    pub fn is_action_released(&self, action: &str) -> bool {
+        self.actions
+            .bindings(action)
+            .any(|binding| match binding {
+                Binding::Key(key) => self.is_key_released(*key),
+                Binding::Mouse(button) => self.is_button_released(*button),
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+///
+/// Maps named actions to any number of keys or mouse buttons
+///
+/// The config file is TOML with one entry per action, for example:
+///
+/// ```toml
+/// [actions]
+/// reseed = [{ key = "Space" }, { mouse = "Middle" }]
+/// ```
+///
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ActionMap {
+    actions: HashMap<String, Vec<Binding>>,
+}
+
+impl ActionMap {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read action map {}", path.display()))?;
+        let map = Self::parse(&source)
+            .with_context(|| format!("Invalid action map {}", path.display()))?;
+
+        info!("Loaded action map {}", path.display());
+
+        Ok(map)
+    }
+
+    pub fn parse(source: &str) -> Result<Self> {
+        Ok(toml::from_str(source)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn bindings(&self, action: &str) -> impl Iterator<Item = &Binding> {
+        self.actions.get(action).into_iter().flatten()
+    }
+
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        let bindings = self.actions.entry(action.to_owned()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn unbind(&mut self, action: &str, binding: Binding) {
+        if let Some(bindings) = self.actions.get_mut(action) {
+            bindings.retain(|b| *b != binding);
+        }
+    }
+
+    ///
+    /// Replaces every binding of an action with a single one
+    ///
+    pub fn rebind(&mut self, action: &str, binding: Binding) {
+        self.actions.insert(action.to_owned(), vec![binding]);
+    }
+}
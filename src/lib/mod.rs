@@ -1,6 +1,23 @@
 pub mod app;
+pub mod clock;
+pub mod compute_shader;
+pub mod debug;
+pub mod effect_params;
 pub mod frame_buffer;
+pub mod fullscreen_pass;
+pub mod gl_state;
+pub mod index_buffer;
+pub mod input;
+pub mod profiler;
+pub mod program_cache;
+pub mod program_pipeline;
+pub mod render_buffer;
+pub mod render_graph;
+pub mod render_target_pool;
 pub mod shader;
+pub mod shader_buffer;
+pub mod shader_variants;
 pub mod texture;
 pub mod vertex_buffer;
 pub mod vertex_array;
+pub mod vertex_layout;
@@ -0,0 +1,398 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    rc::Rc,
+    time::Instant,
+};
+
+use anyhow::Result;
+use egui::{Color32, Sense, Stroke, Ui, pos2, vec2};
+use glow::{HasContext, QUERY_BUFFER, QUERY_RESULT, QUERY_RESULT_AVAILABLE, TIMESTAMP};
+use log::{info, warn};
+
+use crate::gl_state::GlState;
+
+/// Number of frames whose queries can be in flight before their slot is reused
+const FRAMES_IN_FLIGHT: usize = 4;
+/// Number of samples kept for averages, percentiles and the frame graph
+const HISTORY: usize = 240;
+
+///
+/// Rolling window of timings in milliseconds
+///
+#[derive(Default)]
+pub struct Samples {
+    values: VecDeque<f32>,
+}
+
+impl Samples {
+    fn push(&mut self, value: f32) {
+        if self.values.len() == HISTORY {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+    }
+
+    pub fn last(&self) -> Option<f32> {
+        self.values.back().copied()
+    }
+
+    pub fn average(&self) -> Option<f32> {
+        if self.values.is_empty() {
+            return None;
+        }
+        Some(self.values.iter().sum::<f32>() / self.values.len() as f32)
+    }
+
+    ///
+    /// Value below which `percentile` percent of the samples fall, `percentile` going from 0 to 100
+    ///
+    pub fn percentile(&self, percentile: f32) -> Option<f32> {
+        if self.values.is_empty() {
+            return None;
+        }
+
+        let mut sorted: Vec<f32> = self.values.iter().copied().collect();
+        sorted.sort_by(f32::total_cmp);
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * (sorted.len() - 1) as f32).round();
+        Some(sorted[rank as usize])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = f32> + '_ {
+        self.values.iter().copied()
+    }
+}
+
+#[derive(Default)]
+pub struct PassStats {
+    pub cpu: Samples,
+    pub gpu: Samples,
+}
+
+struct OpenPass {
+    name: String,
+    cpu_start: Instant,
+    /// `None` when no query could be created, the pass is then only kept for the nesting
+    start_query: Option<glow::Query>,
+}
+
+struct PendingPass {
+    name: String,
+    cpu_ms: f32,
+    start_query: glow::Query,
+    end_query: glow::Query,
+}
+
+struct State {
+    frames: Vec<Vec<PendingPass>>,
+    frame_index: usize,
+    free_queries: Vec<glow::Query>,
+    open_passes: Vec<OpenPass>,
+    last_frame_start: Option<Instant>,
+    pass_order: Vec<String>,
+    passes: HashMap<String, PassStats>,
+    frame_times: Samples,
+    dropped: u64,
+}
+
+///
+/// Measures CPU and GPU time of named passes without stalling the pipeline
+///
+/// GPU times come from timestamp queries which are read back a few frames later,
+/// so passes can be nested. Results that are still not available when their
+/// slot is reused are dropped.
+///
+pub struct Profiler {
+    state: RefCell<State>,
+    gl: Rc<GlState>,
+}
+
+impl Profiler {
+    pub fn new(gl: Rc<GlState>) -> Self {
+        info!("Initialized profiler");
+
+        Self {
+            state: RefCell::new(State {
+                frames: (0..FRAMES_IN_FLIGHT).map(|_| Vec::new()).collect(),
+                frame_index: 0,
+                free_queries: Vec::new(),
+                open_passes: Vec::new(),
+                last_frame_start: None,
+                pass_order: Vec::new(),
+                passes: HashMap::new(),
+                frame_times: Samples::default(),
+                dropped: 0,
+            }),
+            gl,
+        }
+    }
+
+    ///
+    /// Starts a new frame, collecting every query result that is ready
+    ///
+    pub fn begin_frame(&self) {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+
+        let now = Instant::now();
+        if let Some(last) = state.last_frame_start.replace(now) {
+            state.frame_times.push((now - last).as_secs_f32() * 1000.0);
+        }
+
+        state.frame_index += 1;
+        let slot = state.frame_index % FRAMES_IN_FLIGHT;
+
+        // Oldest frames first, so the slot about to be reused gets the most time
+        for offset in 0..FRAMES_IN_FLIGHT {
+            let index = (slot + offset) % FRAMES_IN_FLIGHT;
+            let frame = std::mem::take(&mut state.frames[index]);
+            let mut remaining = Vec::new();
+
+            for pass in frame {
+                match self.read_pass(&pass) {
+                    Some(gpu_ms) => {
+                        let stats = state.passes.entry(pass.name.clone()).or_default();
+                        stats.cpu.push(pass.cpu_ms);
+                        stats.gpu.push(gpu_ms);
+                        if !state.pass_order.contains(&pass.name) {
+                            state.pass_order.push(pass.name.clone());
+                        }
+                        state.free_queries.push(pass.start_query);
+                        state.free_queries.push(pass.end_query);
+                    }
+                    None if index == slot => {
+                        state.dropped += 1;
+                        state.free_queries.push(pass.start_query);
+                        state.free_queries.push(pass.end_query);
+                    }
+                    None => remaining.push(pass),
+                }
+            }
+
+            state.frames[index] = remaining;
+        }
+    }
+
+    ///
+    /// Ends the frame, any pass left open is closed
+    ///
+    pub fn end_frame(&self) {
+        while !self.state.borrow().open_passes.is_empty() {
+            self.end_pass();
+        }
+    }
+
+    pub fn begin_pass(&self, name: &str) {
+        let start_query = self.take_query().ok();
+        match start_query {
+            Some(query) => unsafe { self.gl.context().query_counter(query, TIMESTAMP) },
+            None => warn!("Unable to create a query for pass {name}"),
+        }
+
+        self.state.borrow_mut().open_passes.push(OpenPass {
+            name: name.to_owned(),
+            cpu_start: Instant::now(),
+            start_query,
+        });
+    }
+
+    pub fn end_pass(&self) {
+        let Some(open) = self.state.borrow_mut().open_passes.pop() else {
+            return;
+        };
+        let cpu_ms = open.cpu_start.elapsed().as_secs_f32() * 1000.0;
+        let Some(start_query) = open.start_query else {
+            return;
+        };
+
+        let Ok(end_query) = self.take_query() else {
+            warn!("Unable to create a query for pass {}", open.name);
+            self.state.borrow_mut().free_queries.push(start_query);
+            return;
+        };
+
+        unsafe {
+            self.gl.context().query_counter(end_query, TIMESTAMP);
+        }
+
+        let mut state = self.state.borrow_mut();
+        let slot = state.frame_index % FRAMES_IN_FLIGHT;
+        state.frames[slot].push(PendingPass {
+            name: open.name,
+            cpu_ms,
+            start_query,
+            end_query,
+        });
+    }
+
+    ///
+    /// Runs `f` inside a pass named `name`
+    ///
+    pub fn scope<T>(&self, name: &str, f: impl FnOnce() -> T) -> T {
+        self.begin_pass(name);
+        let result = f();
+        self.end_pass();
+        result
+    }
+
+    pub fn frame_times(&self) -> std::cell::Ref<'_, Samples> {
+        std::cell::Ref::map(self.state.borrow(), |state| &state.frame_times)
+    }
+
+    pub fn pass(&self, name: &str) -> Option<std::cell::Ref<'_, PassStats>> {
+        std::cell::Ref::filter_map(self.state.borrow(), |state| state.passes.get(name)).ok()
+    }
+
+    ///
+    /// Number of passes whose GPU result was not ready in time
+    ///
+    pub fn dropped(&self) -> u64 {
+        self.state.borrow().dropped
+    }
+
+    ///
+    /// Draws the frame time graph and the per pass breakdown
+    ///
+    pub fn ui(&self, ui: &mut Ui) {
+        let state = self.state.borrow();
+
+        if let (Some(average), Some(p99)) = (
+            state.frame_times.average(),
+            state.frame_times.percentile(99.0),
+        ) {
+            ui.label(format!(
+                "Frame: {average:.2} ms avg, {p99:.2} ms p99 ({:.0} fps)",
+                1000.0 / average
+            ));
+        }
+
+        let (response, painter) =
+            ui.allocate_painter(vec2(ui.available_width(), 60.0), Sense::hover());
+        let rect = response.rect;
+        painter.rect_filled(rect, 2.0, Color32::from_black_alpha(96));
+
+        let max = state
+            .frame_times
+            .iter()
+            .fold(1000.0 / 60.0, f32::max)
+            .max(1.0);
+        let step = rect.width() / (HISTORY - 1) as f32;
+        let points: Vec<_> = state
+            .frame_times
+            .iter()
+            .enumerate()
+            .map(|(i, ms)| {
+                pos2(
+                    rect.left() + i as f32 * step,
+                    rect.bottom() - ms / max * rect.height(),
+                )
+            })
+            .collect();
+        painter.line(points, Stroke::new(1.0, Color32::LIGHT_GREEN));
+
+        let target_y = rect.bottom() - (1000.0 / 60.0) / max * rect.height();
+        painter.hline(
+            rect.x_range(),
+            target_y,
+            Stroke::new(1.0, Color32::from_white_alpha(48)),
+        );
+
+        egui::Grid::new("ProfilerPasses")
+            .num_columns(4)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Pass");
+                ui.label("GPU avg");
+                ui.label("GPU p95");
+                ui.label("CPU avg");
+                ui.end_row();
+
+                for name in &state.pass_order {
+                    let stats = &state.passes[name];
+                    ui.label(name);
+                    ui.label(format_ms(stats.gpu.average()));
+                    ui.label(format_ms(stats.gpu.percentile(95.0)));
+                    ui.label(format_ms(stats.cpu.average()));
+                    ui.end_row();
+                }
+            });
+
+        if state.dropped > 0 {
+            ui.label(format!("Dropped results: {}", state.dropped));
+        }
+    }
+
+    fn take_query(&self) -> Result<glow::Query> {
+        if let Some(query) = self.state.borrow_mut().free_queries.pop() {
+            return Ok(query);
+        }
+
+        unsafe {
+            self.gl
+                .context()
+                .create_query()
+                .map_err(|s| anyhow::anyhow!(s))
+        }
+    }
+
+    fn read_pass(&self, pass: &PendingPass) -> Option<f32> {
+        unsafe {
+            if self
+                .gl
+                .context()
+                .get_query_parameter_u32(pass.end_query, QUERY_RESULT_AVAILABLE)
+                == 0
+            {
+                return None;
+            }
+        }
+
+        let start = self.query_result(pass.start_query);
+        let end = self.query_result(pass.end_query);
+        Some(end.saturating_sub(start) as f32 / 1_000_000.0)
+    }
+
+    ///
+    /// 64 bit result of a query read into client memory
+    ///
+    /// glow only exposes the offset variant, whose offset is a pointer as long as no
+    /// `QUERY_BUFFER` is bound, so the binding is cleared first.
+    ///
+    fn query_result(&self, query: glow::Query) -> u64 {
+        let mut result = 0u64;
+        unsafe {
+            self.gl.context().bind_buffer(QUERY_BUFFER, None);
+            self.gl.context().get_query_parameter_u64_with_offset(
+                query,
+                QUERY_RESULT,
+                &mut result as *mut u64 as usize,
+            );
+        }
+        result
+    }
+}
+
+fn format_ms(value: Option<f32>) -> String {
+    value.map_or_else(|| "-".to_owned(), |ms| format!("{ms:.3} ms"))
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        let state = self.state.get_mut();
+        let pending = state
+            .frames
+            .drain(..)
+            .flatten()
+            .flat_map(|pass| [pass.start_query, pass.end_query]);
+        let open = state
+            .open_passes
+            .drain(..)
+            .filter_map(|pass| pass.start_query);
+
+        for query in pending.chain(open).chain(state.free_queries.drain(..)) {
+            unsafe {
+                self.gl.context().delete_query(query);
+            }
+        }
+    }
+}
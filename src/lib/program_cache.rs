@@ -0,0 +1,210 @@
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use glow::{HasContext, ProgramBinary, RENDERER, VENDOR, VERSION};
+use log::{debug, info, warn};
+
+use crate::{gl_state::GlState, shader::ShaderStage};
+
+/// Start of every cache file, followed by the binary format and the binary
+const MAGIC: &[u8; 4] = b"SGPB";
+
+///
+/// Outcomes of the program lookups since the cache was created
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Programs loaded from a cached binary
+    pub hits: u32,
+    /// Programs without a cached binary
+    pub misses: u32,
+    /// Cached binaries refused by the driver, the program was compiled instead
+    pub rejected: u32,
+    /// Binaries written after compiling
+    pub stored: u32,
+}
+
+///
+/// 64 bit FNV-1a, stable across runs and toolchains unlike the std hasher
+///
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    /// Writes the length first so that consecutive strings cannot be confused
+    fn write_str(&mut self, value: &str) {
+        self.write(&(value.len() as u64).to_le_bytes());
+        self.write(value.as_bytes());
+    }
+}
+
+///
+/// On-disk cache of linked program binaries
+///
+/// Binaries are keyed by the stage sources, which include the injected defines, and by
+/// the GL vendor, renderer and version, a driver update giving new keys. A binary that
+/// cannot be read or is refused by the driver is a miss, the program is then compiled
+/// from source and stored again.
+///
+pub struct ProgramCache {
+    dir: PathBuf,
+    /// Vendor, renderer and version of the context
+    driver: String,
+    stats: RefCell<CacheStats>,
+}
+
+impl ProgramCache {
+    pub fn new(gl: &GlState, dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Unable to create program cache {}", dir.display()))?;
+
+        let driver = unsafe {
+            format!(
+                "{}\n{}\n{}",
+                gl.context().get_parameter_string(VENDOR),
+                gl.context().get_parameter_string(RENDERER),
+                gl.context().get_parameter_string(VERSION)
+            )
+        };
+
+        info!("Initialized program cache in {}", dir.display());
+
+        Ok(Self {
+            dir,
+            driver,
+            stats: RefCell::new(CacheStats::default()),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub(crate) fn key(&self, stages: &[(ShaderStage, &str)], separable: bool) -> u64 {
+        let mut hasher = Fnv::new();
+        hasher.write_str(&self.driver);
+        hasher.write(&[separable as u8]);
+        for (stage, source) in stages {
+            hasher.write(&stage.gl_type().to_le_bytes());
+            hasher.write_str(source);
+        }
+        hasher.0
+    }
+
+    ///
+    /// Cached binary of `key`, counted as a miss when there is none
+    ///
+    pub(crate) fn load(&self, key: u64) -> Option<ProgramBinary> {
+        let path = self.path(key);
+        let binary = fs::read(&path).ok().and_then(|data| {
+            let (magic, rest) = data.split_first_chunk::<4>()?;
+            let (format, buffer) = rest.split_first_chunk::<4>()?;
+            (magic == MAGIC).then(|| ProgramBinary {
+                buffer: buffer.to_vec(),
+                format: u32::from_le_bytes(*format),
+            })
+        });
+
+        if binary.is_none() {
+            self.stats.borrow_mut().misses += 1;
+            debug!("Program cache miss for {}", path.display());
+        }
+        binary
+    }
+
+    ///
+    /// Counts a binary given by `load` as a hit if the driver accepted it, otherwise drops it
+    ///
+    pub(crate) fn loaded(&self, key: u64, accepted: bool) {
+        let mut stats = self.stats.borrow_mut();
+        if accepted {
+            stats.hits += 1;
+        } else {
+            stats.rejected += 1;
+            debug!("Program cache binary {key:016x} rejected by the driver");
+            let _ = fs::remove_file(self.path(key));
+        }
+    }
+
+    ///
+    /// Writes a binary, failures are only logged since the cache is an optimization
+    ///
+    pub(crate) fn store(&self, key: u64, binary: &ProgramBinary) {
+        let mut data = Vec::with_capacity(binary.buffer.len() + 8);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&binary.format.to_le_bytes());
+        data.extend_from_slice(&binary.buffer);
+
+        let path = self.path(key);
+        match fs::write(&path, data) {
+            Ok(()) => self.stats.borrow_mut().stored += 1,
+            Err(error) => warn!("Unable to write program binary {}: {error}", path.display()),
+        }
+    }
+
+    ///
+    /// Deletes every cached binary
+    ///
+    pub fn clear(&self) -> Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "bin") {
+                fs::remove_file(&path)
+                    .with_context(|| format!("Unable to remove {}", path.display()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        *self.stats.borrow()
+    }
+
+    ///
+    /// Draws the lookup counters and a button clearing the cache
+    ///
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        let stats = self.stats();
+        egui::Grid::new("ProgramCache")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Hits");
+                ui.label(stats.hits.to_string());
+                ui.end_row();
+                ui.label("Misses");
+                ui.label(stats.misses.to_string());
+                ui.end_row();
+                ui.label("Rejected");
+                ui.label(stats.rejected.to_string());
+                ui.end_row();
+                ui.label("Stored");
+                ui.label(stats.stored.to_string());
+                ui.end_row();
+            });
+        if ui.button("Clear").clicked()
+            && let Err(error) = self.clear()
+        {
+            warn!("{error:?}");
+        }
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{key:016x}.bin"))
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    ffi::{CStr, c_void},
+    num::NonZeroU32,
+    rc::Rc,
+};
+
+use anyhow::Result;
+use log::info;
+
+use crate::{
+    debug,
+    gl_state::GlState,
+    shader::{Shader, ShaderStage},
+};
+
+///
+/// Entry points of separable programs and program pipelines, which glow does not expose
+///
+pub(crate) struct PipelineFns {
+    program_parameteri: unsafe extern "system" fn(u32, u32, i32),
+    create_program_pipelines: unsafe extern "system" fn(i32, *mut u32),
+    delete_program_pipelines: unsafe extern "system" fn(i32, *const u32),
+    bind_program_pipeline: unsafe extern "system" fn(u32),
+    use_program_stages: unsafe extern "system" fn(u32, u32, u32),
+}
+
+impl PipelineFns {
+    ///
+    /// Loads the entry points, `None` when the driver lacks one of them
+    ///
+    pub(crate) fn load(loader: impl Fn(&CStr) -> *const c_void) -> Option<Self> {
+        macro_rules! load {
+            ($name:literal: $type:ty) => {{
+                let function = loader($name);
+                if function.is_null() {
+                    return None;
+                }
+                // SAFETY: the GL specification gives the signature of the named entry point
+                unsafe { std::mem::transmute::<*const c_void, $type>(function) }
+            }};
+        }
+
+        Some(Self {
+            program_parameteri: load!(
+                c"glProgramParameteri": unsafe extern "system" fn(u32, u32, i32)
+            ),
+            create_program_pipelines: load!(
+                c"glCreateProgramPipelines": unsafe extern "system" fn(i32, *mut u32)
+            ),
+            delete_program_pipelines: load!(
+                c"glDeleteProgramPipelines": unsafe extern "system" fn(i32, *const u32)
+            ),
+            bind_program_pipeline: load!(c"glBindProgramPipeline": unsafe extern "system" fn(u32)),
+            use_program_stages: load!(
+                c"glUseProgramStages": unsafe extern "system" fn(u32, u32, u32)
+            ),
+        })
+    }
+
+    pub(crate) unsafe fn program_parameter_i32(
+        &self,
+        program: glow::Program,
+        name: u32,
+        value: i32,
+    ) {
+        unsafe { (self.program_parameteri)(program.0.get(), name, value) }
+    }
+
+    pub(crate) unsafe fn bind_program_pipeline(&self, pipeline: Option<NonZeroU32>) {
+        unsafe { (self.bind_program_pipeline)(pipeline.map_or(0, NonZeroU32::get)) }
+    }
+}
+
+///
+/// Set of separable programs, each one providing some of the stages
+///
+/// Programs can be swapped stage by stage without linking again, e.g. to pair one
+/// vertex program with several fragment programs.
+///
+pub struct ProgramPipeline {
+    pub id: NonZeroU32,
+    gl: Rc<GlState>,
+}
+
+impl ProgramPipeline {
+    pub fn new(gl: Rc<GlState>) -> Result<Self> {
+        let fns = gl.pipeline_fns()?;
+        let mut id = 0;
+        unsafe { (fns.create_program_pipelines)(1, &mut id) };
+        let id = NonZeroU32::new(id)
+            .ok_or_else(|| anyhow::anyhow!("Unable to create program pipeline"))?;
+
+        info!("Initialized program pipeline {id:?}");
+
+        Ok(Self { id, gl })
+    }
+
+    ///
+    /// Uses every stage of a separable program
+    ///
+    pub fn use_program(&self, program: &Shader) -> Result<()> {
+        self.use_program_stages(program.stages(), program)
+    }
+
+    ///
+    /// Uses some stages of a separable program, replacing the programs previously used for them
+    ///
+    pub fn use_program_stages(&self, stages: &[ShaderStage], program: &Shader) -> Result<()> {
+        if !program.is_separable() {
+            return Err(anyhow::anyhow!(
+                "Program {:?} is not separable and cannot be used in pipeline {:?}",
+                program.id,
+                self.id
+            ));
+        }
+        if let Some(stage) = stages
+            .iter()
+            .find(|stage| !program.stages().contains(stage))
+        {
+            return Err(anyhow::anyhow!(
+                "Program {:?} has no {} stage",
+                program.id,
+                stage.name()
+            ));
+        }
+
+        let bits = stages.iter().fold(0, |bits, stage| bits | stage.bit());
+        let fns = self.gl.pipeline_fns()?;
+        unsafe { (fns.use_program_stages)(self.id.get(), bits, program.id.0.get()) };
+
+        Ok(())
+    }
+
+    ///
+    /// Removes the program of some stages
+    ///
+    pub fn clear_stages(&self, stages: &[ShaderStage]) -> Result<()> {
+        let bits = stages.iter().fold(0, |bits, stage| bits | stage.bit());
+        let fns = self.gl.pipeline_fns()?;
+        unsafe { (fns.use_program_stages)(self.id.get(), bits, 0) };
+
+        Ok(())
+    }
+
+    ///
+    /// Draws with this pipeline, a program bound with `use_program` would take precedence
+    /// so it is unbound
+    ///
+    pub fn bind(&self) {
+        self.gl.set_program(None);
+        self.gl.set_program_pipeline(Some(self.id));
+    }
+
+    ///
+    /// Names this pipeline in GL debug messages
+    ///
+    pub fn set_label(&self, label: &str) {
+        debug::set_label(&self.gl, glow::PROGRAM_PIPELINE, self.id.get(), label);
+    }
+}
+
+impl Drop for ProgramPipeline {
+    fn drop(&mut self) {
+        self.gl.forget_program_pipeline(self.id);
+        if let Ok(fns) = self.gl.pipeline_fns() {
+            unsafe { (fns.delete_program_pipelines)(1, &self.id.get()) };
+        }
+    }
+}
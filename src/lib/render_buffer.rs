@@ -0,0 +1,115 @@
+use std::rc::Rc;
+
+use anyhow::Result;
+use glow::{HasContext, RENDERBUFFER};
+use log::info;
+
+use crate::{debug, gl_state::GlState};
+
+///
+/// Render target storage that can be drawn to but not sampled
+///
+pub struct RenderBuffer {
+    pub id: glow::Renderbuffer,
+    format: u32,
+    /// Samples per pixel, 0 for a single sampled render buffer
+    samples: i32,
+    label: Option<String>,
+    gl: Rc<GlState>,
+}
+
+impl RenderBuffer {
+    pub fn new(gl: Rc<GlState>, width: i32, height: i32, format: u32) -> Result<Self> {
+        Self::multisample(gl, width, height, format, 0)
+    }
+
+    ///
+    /// Render buffer storing `samples` values per pixel, less than 2 samples gives a regular one
+    ///
+    pub fn multisample(
+        gl: Rc<GlState>,
+        width: i32,
+        height: i32,
+        format: u32,
+        samples: i32,
+    ) -> Result<Self> {
+        let samples = if samples > 1 { samples } else { 0 };
+        let id = Self::create(&gl, width, height, format, samples)?;
+
+        info!("Initialized render buffer {id:?}");
+
+        Ok(Self {
+            id,
+            format,
+            samples,
+            label: None,
+            gl,
+        })
+    }
+
+    pub fn resize(&mut self, width: i32, height: i32) -> Result<()> {
+        unsafe {
+            self.gl.context().delete_renderbuffer(self.id);
+        }
+        self.id = Self::create(&self.gl, width, height, self.format, self.samples)?;
+
+        if let Some(label) = &self.label {
+            debug::set_label(&self.gl, RENDERBUFFER, self.id.0.get(), label);
+        }
+
+        info!("Resized render buffer {:?}", self.id);
+
+        Ok(())
+    }
+
+    ///
+    /// Names this render buffer in GL debug messages, the label is kept across resizes
+    ///
+    pub fn set_label(&mut self, label: &str) {
+        debug::set_label(&self.gl, RENDERBUFFER, self.id.0.get(), label);
+        self.label = Some(label.to_owned());
+    }
+
+    pub fn samples(&self) -> i32 {
+        self.samples
+    }
+
+    fn create(
+        gl: &GlState,
+        width: i32,
+        height: i32,
+        format: u32,
+        samples: i32,
+    ) -> Result<glow::Renderbuffer> {
+        unsafe {
+            let id = gl
+                .context()
+                .create_renderbuffer()
+                .or_else(|s| Err(anyhow::anyhow!(s)))?;
+            gl.context().bind_renderbuffer(RENDERBUFFER, Some(id));
+            if samples > 1 {
+                gl.context().renderbuffer_storage_multisample(
+                    RENDERBUFFER,
+                    samples,
+                    format,
+                    width,
+                    height,
+                );
+            } else {
+                gl.context()
+                    .renderbuffer_storage(RENDERBUFFER, format, width, height);
+            }
+            gl.context().bind_renderbuffer(RENDERBUFFER, None);
+
+            Ok(id)
+        }
+    }
+}
+
+impl Drop for RenderBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.context().delete_renderbuffer(self.id);
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use std::{collections::HashSet, fmt::Write, rc::Rc};
+
+use anyhow::{Context, Result};
+use log::debug;
+
+use crate::{
+    compute_shader::{Barrier, memory_barrier},
+    frame_buffer::{FrameBuffer, FrameBufferDesc},
+    fullscreen_pass::Viewport,
+    gl_state::GlState,
+    profiler::Profiler,
+    render_target_pool::{RenderTarget, RenderTargetPool},
+    texture::Texture,
+};
+
+///
+/// Handle to a frame buffer of a `RenderGraph`
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceId(usize);
+
+enum ResourceKind<'a> {
+    /// Taken from the pool for the passes using it, then given back
+    Transient(FrameBufferDesc),
+    /// Owned outside the graph, `None` being the default frame buffer
+    Imported(Option<&'a FrameBuffer>, Viewport),
+}
+
+struct ResourceNode<'a> {
+    name: String,
+    kind: ResourceKind<'a>,
+}
+
+type Execute<'a> = Box<dyn FnOnce(&PassContext) -> Result<()> + 'a>;
+
+struct PassNode<'a> {
+    name: String,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+    execute: Execute<'a>,
+}
+
+///
+/// Order, culling and lifetimes computed from the declared reads and writes
+///
+struct Schedule {
+    /// Indices of the passes to run, in order
+    order: Vec<usize>,
+    /// Position in `order` of the first and last pass using each transient resource
+    lifetimes: Vec<Option<(usize, usize)>>,
+}
+
+///
+/// Frame described as passes reading and writing frame buffers
+///
+/// The graph is built every frame. Passes only declare the resources they use, and
+/// `execute` runs them in dependency order, skips the ones whose output is never used,
+/// and takes transient frame buffers from a `RenderTargetPool` only while they are
+/// needed so that passes with matching descriptions end up sharing them.
+///
+/// A pass reading a resource runs after every pass writing it. Passes that do not
+/// contribute to an imported resource are culled.
+///
+pub struct RenderGraph<'a> {
+    resources: Vec<ResourceNode<'a>>,
+    passes: Vec<PassNode<'a>>,
+    gl: Rc<GlState>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new(gl: Rc<GlState>) -> Self {
+        Self {
+            resources: Vec::new(),
+            passes: Vec::new(),
+            gl,
+        }
+    }
+
+    ///
+    /// Declares a frame buffer that only lives during the frame
+    ///
+    pub fn create(&mut self, name: &str, desc: FrameBufferDesc) -> ResourceId {
+        self.add_resource(name, ResourceKind::Transient(desc))
+    }
+
+    ///
+    /// Declares a frame buffer owned outside the graph, its content is kept after the frame
+    ///
+    pub fn import(&mut self, name: &str, frame_buffer: &'a FrameBuffer) -> ResourceId {
+        let viewport = frame_buffer.viewport();
+        self.add_resource(name, ResourceKind::Imported(Some(frame_buffer), viewport))
+    }
+
+    ///
+    /// Declares the default frame buffer, drawn to over `viewport`
+    ///
+    pub fn import_default(&mut self, name: &str, viewport: Viewport) -> ResourceId {
+        self.add_resource(name, ResourceKind::Imported(None, viewport))
+    }
+
+    pub fn add_pass(
+        &mut self,
+        name: &str,
+        reads: &[ResourceId],
+        writes: &[ResourceId],
+        execute: impl FnOnce(&PassContext) -> Result<()> + 'a,
+    ) {
+        self.passes.push(PassNode {
+            name: name.to_owned(),
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            execute: Box::new(execute),
+        });
+    }
+
+    ///
+    /// Runs the passes, each one inside a profiler scope of its name
+    ///
+    pub fn execute(self, pool: &RenderTargetPool, profiler: &Profiler) -> Result<()> {
+        let schedule = self.schedule()?;
+        let mut passes: Vec<Option<PassNode>> = self.passes.into_iter().map(Some).collect();
+        let mut targets: Vec<Option<RenderTarget>> = self.resources.iter().map(|_| None).collect();
+        let mut written = HashSet::new();
+
+        for (step, &index) in schedule.order.iter().enumerate() {
+            let pass = passes[index].take().unwrap();
+
+            for (id, lifetime) in schedule.lifetimes.iter().enumerate() {
+                if let (Some((first, _)), ResourceKind::Transient(desc)) =
+                    (lifetime, &self.resources[id].kind)
+                    && *first == step
+                {
+                    targets[id] = Some(pool.acquire(desc, &self.resources[id].name)?);
+                }
+            }
+
+            // Conservative, also covers the passes reading what another pass stored with image writes
+            if pass.reads.iter().any(|id| written.contains(id)) {
+                memory_barrier(
+                    &self.gl,
+                    Barrier::TEXTURE_FETCH | Barrier::IMAGE_ACCESS | Barrier::FRAMEBUFFER,
+                );
+                written.clear();
+            }
+
+            let context = PassContext {
+                resources: &self.resources,
+                targets: &targets,
+            };
+            profiler.scope(&pass.name, || (pass.execute)(&context))?;
+            written.extend(pass.writes.iter().copied());
+
+            for (id, lifetime) in schedule.lifetimes.iter().enumerate() {
+                if let Some((_, last)) = lifetime
+                    && *last == step
+                {
+                    targets[id] = None;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Graphviz description of the frame, culled passes are dashed
+    ///
+    pub fn to_dot(&self) -> Result<String> {
+        let schedule = self.schedule()?;
+        let mut dot = String::from("digraph RenderGraph {\n    rankdir=LR;\n");
+
+        for (id, resource) in self.resources.iter().enumerate() {
+            let label = match &resource.kind {
+                ResourceKind::Transient(desc) => format!(
+                    "{}\\n{}x{} x{}",
+                    resource.name,
+                    desc.width,
+                    desc.height,
+                    desc.samples.max(1)
+                ),
+                ResourceKind::Imported(..) => format!("{}\\nimported", resource.name),
+            };
+            writeln!(dot, "    r{id} [shape=ellipse, label=\"{label}\"];")?;
+        }
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            let label = match schedule.order.iter().position(|&i| i == index) {
+                Some(step) => format!("{step}: {}", pass.name),
+                None => pass.name.clone(),
+            };
+            let style = if schedule.order.contains(&index) {
+                "solid"
+            } else {
+                "dashed"
+            };
+            writeln!(
+                dot,
+                "    p{index} [shape=box, style={style}, label=\"{label}\"];"
+            )?;
+            for read in &pass.reads {
+                writeln!(dot, "    r{} -> p{index};", read.0)?;
+            }
+            for write in &pass.writes {
+                writeln!(dot, "    p{index} -> r{};", write.0)?;
+            }
+        }
+
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+
+    pub fn write_dot(&self, path: &str) -> Result<()> {
+        std::fs::write(path, self.to_dot()?)
+            .with_context(|| format!("Unable to write render graph to {path}"))
+    }
+
+    fn add_resource(&mut self, name: &str, kind: ResourceKind<'a>) -> ResourceId {
+        self.resources.push(ResourceNode {
+            name: name.to_owned(),
+            kind,
+        });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    fn schedule(&self) -> Result<Schedule> {
+        for pass in &self.passes {
+            if let Some(id) = pass
+                .reads
+                .iter()
+                .chain(&pass.writes)
+                .find(|id| id.0 >= self.resources.len())
+            {
+                return Err(anyhow::anyhow!(
+                    "Pass {} uses unknown resource {id:?}",
+                    pass.name
+                ));
+            }
+        }
+
+        // Every reader depends on every writer of the resource, writers keep their submission order
+        let mut dependencies: Vec<Vec<usize>> = vec![Vec::new(); self.passes.len()];
+        for (index, pass) in self.passes.iter().enumerate() {
+            for (other, writer) in self.passes.iter().enumerate() {
+                let reads_output = pass.reads.iter().any(|id| writer.writes.contains(id));
+                let earlier_writer =
+                    other < index && pass.writes.iter().any(|id| writer.writes.contains(id));
+                if other != index && (reads_output || earlier_writer) {
+                    dependencies[index].push(other);
+                }
+            }
+        }
+
+        // Keep the passes contributing to an imported resource
+        let mut alive = vec![false; self.passes.len()];
+        let mut stack: Vec<usize> = (0..self.passes.len())
+            .filter(|&index| {
+                self.passes[index]
+                    .writes
+                    .iter()
+                    .any(|id| matches!(self.resources[id.0].kind, ResourceKind::Imported(..)))
+            })
+            .collect();
+        while let Some(index) = stack.pop() {
+            if !alive[index] {
+                alive[index] = true;
+                stack.extend(&dependencies[index]);
+            }
+        }
+
+        // Kahn's algorithm, picking the earliest submitted pass when several are ready
+        let mut remaining: Vec<usize> = dependencies
+            .iter()
+            .map(|dependencies| dependencies.iter().filter(|&&d| alive[d]).count())
+            .collect();
+        let mut done = vec![false; self.passes.len()];
+        let mut order = Vec::new();
+        let alive_count = alive.iter().filter(|&&alive| alive).count();
+
+        while order.len() < alive_count {
+            let next = (0..self.passes.len())
+                .find(|&index| alive[index] && !done[index] && remaining[index] == 0);
+            let Some(next) = next else {
+                let cycle: Vec<&str> = (0..self.passes.len())
+                    .filter(|&index| alive[index] && !done[index])
+                    .map(|index| self.passes[index].name.as_str())
+                    .collect();
+                return Err(anyhow::anyhow!(
+                    "Render graph has a cycle between passes {cycle:?}"
+                ));
+            };
+
+            done[next] = true;
+            order.push(next);
+            for (index, dependencies) in dependencies.iter().enumerate() {
+                if dependencies.contains(&next) {
+                    remaining[index] -= 1;
+                }
+            }
+        }
+
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.resources.len()];
+        for (step, &index) in order.iter().enumerate() {
+            let pass = &self.passes[index];
+            for id in pass.reads.iter().chain(&pass.writes) {
+                if let ResourceKind::Transient(_) = self.resources[id.0].kind {
+                    let lifetime = lifetimes[id.0].get_or_insert((step, step));
+                    lifetime.1 = step;
+                }
+            }
+        }
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            if !alive[index] {
+                debug!("Culled render pass {}", pass.name);
+            }
+        }
+
+        Ok(Schedule { order, lifetimes })
+    }
+}
+
+///
+/// Resources of the graph as seen by a running pass
+///
+pub struct PassContext<'r> {
+    resources: &'r [ResourceNode<'r>],
+    targets: &'r [Option<RenderTarget<'r>>],
+}
+
+impl PassContext<'_> {
+    ///
+    /// Frame buffer to draw to, `None` for the default frame buffer
+    ///
+    pub fn frame_buffer(&self, id: ResourceId) -> Option<&FrameBuffer> {
+        match &self.resources[id.0].kind {
+            ResourceKind::Transient(_) => self.targets[id.0].as_deref(),
+            ResourceKind::Imported(frame_buffer, _) => *frame_buffer,
+        }
+    }
+
+    pub fn viewport(&self, id: ResourceId) -> Viewport {
+        match &self.resources[id.0].kind {
+            ResourceKind::Transient(desc) => Viewport::new(0, 0, desc.width, desc.height),
+            ResourceKind::Imported(_, viewport) => *viewport,
+        }
+    }
+
+    ///
+    /// Color texture `index` of a resource, which must have been declared as read or written
+    ///
+    pub fn texture(&self, id: ResourceId, index: usize) -> Result<&Texture> {
+        self.frame_buffer(id)
+            .and_then(|frame_buffer| frame_buffer.texture(index))
+            .with_context(|| {
+                format!(
+                    "Resource {} has no color texture {index}",
+                    self.resources[id.0].name
+                )
+            })
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    rc::Rc,
+};
+
+use anyhow::Result;
+use log::info;
+
+use crate::{
+    frame_buffer::{FrameBuffer, FrameBufferDesc},
+    gl_state::GlState,
+};
+
+/// Frames a free target is kept before being deleted
+const DEFAULT_MAX_AGE: u64 = 120;
+
+///
+/// Counters of the pool, the totals are counted since its creation
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Frame buffers created because no free one matched
+    pub allocated: u64,
+    /// Requests served by a free frame buffer
+    pub reused: u64,
+    /// Frame buffers deleted after staying unused too long
+    pub evicted: u64,
+    /// Frame buffers currently borrowed
+    pub in_use: usize,
+    /// Frame buffers waiting to be reused
+    pub free: usize,
+}
+
+struct FreeTarget {
+    frame_buffer: FrameBuffer,
+    last_used: u64,
+}
+
+struct State {
+    free: HashMap<FrameBufferDesc, Vec<FreeTarget>>,
+    frame: u64,
+    stats: PoolStats,
+}
+
+///
+/// Recycles transient frame buffers between passes and frames
+///
+/// Targets are keyed by their whole description, so size, formats and sample count
+/// must all match to be reused. A target borrowed with `acquire` goes back to the pool
+/// when dropped, and free targets unused for `max_age` frames are deleted by `end_frame`.
+///
+pub struct RenderTargetPool {
+    state: RefCell<State>,
+    max_age: u64,
+    gl: Rc<GlState>,
+}
+
+impl RenderTargetPool {
+    pub fn new(gl: Rc<GlState>) -> Self {
+        info!("Initialized render target pool");
+
+        Self {
+            state: RefCell::new(State {
+                free: HashMap::new(),
+                frame: 0,
+                stats: PoolStats::default(),
+            }),
+            max_age: DEFAULT_MAX_AGE,
+            gl,
+        }
+    }
+
+    ///
+    /// Number of frames a free target survives, 0 deletes them at the end of every frame
+    ///
+    pub fn with_max_age(mut self, frames: u64) -> Self {
+        self.max_age = frames;
+        self
+    }
+
+    ///
+    /// Borrows a frame buffer matching `desc`, creating it when none is free
+    ///
+    /// `label` names the frame buffer in GL debug messages when it is created, a reused
+    /// one keeps the name it was created with.
+    ///
+    pub fn acquire(&self, desc: &FrameBufferDesc, label: &str) -> Result<RenderTarget<'_>> {
+        let reused = {
+            let mut state = self.state.borrow_mut();
+            let frame_buffer = state
+                .free
+                .get_mut(desc)
+                .and_then(Vec::pop)
+                .map(|free| free.frame_buffer);
+            if frame_buffer.is_some() {
+                state.stats.reused += 1;
+                state.stats.free -= 1;
+            }
+            frame_buffer
+        };
+
+        let frame_buffer = match reused {
+            Some(frame_buffer) => frame_buffer,
+            None => {
+                let mut frame_buffer = FrameBuffer::from_desc(self.gl.clone(), desc.clone())?;
+                frame_buffer.set_label(label);
+                self.state.borrow_mut().stats.allocated += 1;
+                frame_buffer
+            }
+        };
+        self.state.borrow_mut().stats.in_use += 1;
+
+        Ok(RenderTarget {
+            frame_buffer: Some(frame_buffer),
+            pool: self,
+        })
+    }
+
+    ///
+    /// Ages the free targets and deletes the ones that were not reused in time
+    ///
+    pub fn end_frame(&self) {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        state.frame += 1;
+
+        let mut evicted = 0;
+        for targets in state.free.values_mut() {
+            let count = targets.len();
+            targets.retain(|free| state.frame - free.last_used <= self.max_age);
+            evicted += count - targets.len();
+        }
+        state.free.retain(|_, targets| !targets.is_empty());
+
+        if evicted > 0 {
+            state.stats.evicted += evicted as u64;
+            state.stats.free -= evicted;
+            info!("Evicted {evicted} render targets");
+        }
+    }
+
+    ///
+    /// Deletes every free target
+    ///
+    pub fn clear(&self) {
+        let mut state = self.state.borrow_mut();
+        let count = state.stats.free;
+        state.free.clear();
+        state.stats.evicted += count as u64;
+        state.stats.free = 0;
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.state.borrow().stats
+    }
+
+    ///
+    /// Draws the pool counters
+    ///
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        let stats = self.stats();
+        egui::Grid::new("RenderTargetPool")
+            .num_columns(2)
+            .show(ui, |ui| {
+                for (name, value) in [
+                    ("In use", stats.in_use as u64),
+                    ("Free", stats.free as u64),
+                    ("Allocated", stats.allocated),
+                    ("Reused", stats.reused),
+                    ("Evicted", stats.evicted),
+                ] {
+                    ui.label(name);
+                    ui.label(value.to_string());
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn release(&self, frame_buffer: FrameBuffer) {
+        let mut state = self.state.borrow_mut();
+        let last_used = state.frame;
+        state.stats.in_use -= 1;
+        state.stats.free += 1;
+        state
+            .free
+            .entry(frame_buffer.desc().clone())
+            .or_default()
+            .push(FreeTarget {
+                frame_buffer,
+                last_used,
+            });
+    }
+}
+
+///
+/// Frame buffer borrowed from a `RenderTargetPool`, given back when dropped
+///
+pub struct RenderTarget<'a> {
+    frame_buffer: Option<FrameBuffer>,
+    pool: &'a RenderTargetPool,
+}
+
+impl Deref for RenderTarget<'_> {
+    type Target = FrameBuffer;
+
+    fn deref(&self) -> &FrameBuffer {
+        self.frame_buffer.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for RenderTarget<'_> {
+    fn deref_mut(&mut self) -> &mut FrameBuffer {
+        self.frame_buffer.as_mut().unwrap()
+    }
+}
+
+impl Drop for RenderTarget<'_> {
+    fn drop(&mut self) {
+        if let Some(frame_buffer) = self.frame_buffer.take() {
+            self.pool.release(frame_buffer);
+        }
+    }
+}
@@ -1,62 +1,468 @@
-use std::sync::Arc;
+use std::{fmt::Write, fs, rc::Rc};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use glm::Vec2;
-use glow::{HasContext, UniformLocation};
+use glow::{HasContext, ProgramBinary, UniformLocation};
 use log::info;
 
+use crate::{debug, gl_state::GlState};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderStage {
+    Vertex,
+    TessControl,
+    TessEvaluation,
+    Geometry,
+    Fragment,
+    Compute,
+}
+
+impl ShaderStage {
+    pub fn gl_type(self) -> u32 {
+        match self {
+            ShaderStage::Vertex => glow::VERTEX_SHADER,
+            ShaderStage::TessControl => glow::TESS_CONTROL_SHADER,
+            ShaderStage::TessEvaluation => glow::TESS_EVALUATION_SHADER,
+            ShaderStage::Geometry => glow::GEOMETRY_SHADER,
+            ShaderStage::Fragment => glow::FRAGMENT_SHADER,
+            ShaderStage::Compute => glow::COMPUTE_SHADER,
+        }
+    }
+
+    ///
+    /// Bit of the stage in a program pipeline
+    ///
+    pub fn bit(self) -> u32 {
+        match self {
+            ShaderStage::Vertex => glow::VERTEX_SHADER_BIT,
+            ShaderStage::TessControl => glow::TESS_CONTROL_SHADER_BIT,
+            ShaderStage::TessEvaluation => glow::TESS_EVALUATION_SHADER_BIT,
+            ShaderStage::Geometry => glow::GEOMETRY_SHADER_BIT,
+            ShaderStage::Fragment => glow::FRAGMENT_SHADER_BIT,
+            ShaderStage::Compute => glow::COMPUTE_SHADER_BIT,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ShaderStage::Vertex => "Vertex",
+            ShaderStage::TessControl => "Tessellation control",
+            ShaderStage::TessEvaluation => "Tessellation evaluation",
+            ShaderStage::Geometry => "Geometry",
+            ShaderStage::Fragment => "Fragment",
+            ShaderStage::Compute => "Compute",
+        }
+    }
+}
+
+#[derive(Clone)]
+struct StageSource {
+    stage: ShaderStage,
+    source: String,
+    /// Shown in compile errors
+    file: Option<String>,
+}
+
+fn compile(gl: &GlState, stage: &StageSource) -> Result<glow::Shader> {
+    unsafe {
+        let shader = gl
+            .context()
+            .create_shader(stage.stage.gl_type())
+            .or_else(|s| Err(anyhow::anyhow!(s)))?;
+        gl.context().shader_source(shader, &stage.source);
+        gl.context().compile_shader(shader);
+        if !gl.context().get_shader_compile_status(shader) {
+            let log = gl.context().get_shader_info_log(shader);
+            gl.context().delete_shader(shader);
+            return Err(match &stage.file {
+                Some(file) => anyhow::anyhow!(
+                    "{} shader compile error in {file}: {log}",
+                    stage.stage.name()
+                ),
+                None => anyhow::anyhow!("{} shader compile error: {log}", stage.stage.name()),
+            });
+        }
+
+        Ok(shader)
+    }
+}
+
+///
+/// Inserts `#define`s after the `#version` directive, which must come first, and resets
+/// the line numbers so that compile errors point to the original lines
+///
+fn inject_defines(source: &str, defines: &[(String, String)]) -> String {
+    if defines.is_empty() {
+        return source.to_owned();
+    }
+
+    let lines: Vec<&str> = source.lines().collect();
+    let start = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with("#version"))
+        .map_or(0, |index| index + 1);
+
+    let mut result = String::with_capacity(source.len() + defines.len() * 32);
+    for line in &lines[..start] {
+        result.push_str(line);
+        result.push('\n');
+    }
+    for (name, value) in defines {
+        let _ = writeln!(result, "#define {name} {value}");
+    }
+    let _ = writeln!(result, "#line {}", start + 1);
+    for line in &lines[start..] {
+        result.push_str(line);
+        result.push('\n');
+    }
+    result
+}
+
+///
+/// Program made of any valid combination of stages
+///
+/// ```ignore
+/// let shader = ShaderProgramBuilder::new(gl)
+///     .stage_file(ShaderStage::Vertex, "VS.glsl", include_str!("VS.glsl"))
+///     .stage_file(ShaderStage::Geometry, "GS.glsl", include_str!("GS.glsl"))
+///     .stage_file(ShaderStage::Fragment, "FS.glsl", include_str!("FS.glsl"))
+///     .build()?;
+/// ```
+///
+#[derive(Clone)]
+pub struct ShaderProgramBuilder {
+    gl: Rc<GlState>,
+    stages: Vec<StageSource>,
+    /// Name and value of the `#define`s added to every stage
+    defines: Vec<(String, String)>,
+    separable: bool,
+    label: Option<String>,
+}
+
+impl ShaderProgramBuilder {
+    pub fn new(gl: Rc<GlState>) -> Self {
+        Self {
+            gl,
+            stages: Vec::new(),
+            defines: Vec::new(),
+            separable: false,
+            label: None,
+        }
+    }
+
+    pub fn stage(mut self, stage: ShaderStage, source: &str) -> Self {
+        self.stages.push(StageSource {
+            stage,
+            source: source.to_owned(),
+            file: None,
+        });
+        self
+    }
+
+    ///
+    /// Adds a stage whose source comes from `file`, named in its compile errors
+    ///
+    pub fn stage_file(mut self, stage: ShaderStage, file: &str, source: &str) -> Self {
+        self.stages.push(StageSource {
+            stage,
+            source: source.to_owned(),
+            file: Some(file.to_owned()),
+        });
+        self
+    }
+
+    ///
+    /// Adds a stage read from the file at `path`
+    ///
+    pub fn load(self, stage: ShaderStage, path: &str) -> Result<Self> {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read {} shader {path}", stage.name()))?;
+        Ok(self.stage_file(stage, path, &source))
+    }
+
+    ///
+    /// Allows the program to be used in a `ProgramPipeline`
+    ///
+    pub fn separable(mut self, separable: bool) -> Self {
+        self.separable = separable;
+        self
+    }
+
+    ///
+    /// Defines a macro in every stage, right after its `#version` directive
+    ///
+    pub fn define(mut self, name: &str, value: &str) -> Self {
+        self.defines.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_owned());
+        self
+    }
+
+    pub fn build(mut self) -> Result<Shader> {
+        self.validate()?;
+
+        // Preprocessed sources, so that the defines are part of the cache key
+        for stage in &mut self.stages {
+            stage.source = inject_defines(&stage.source, &self.defines);
+        }
+
+        let stages: Vec<ShaderStage> = self.stages.iter().map(|stage| stage.stage).collect();
+        let cache = self.gl.program_cache();
+        let key = cache.map(|cache| {
+            let sources: Vec<(ShaderStage, &str)> = self
+                .stages
+                .iter()
+                .map(|stage| (stage.stage, stage.source.as_str()))
+                .collect();
+            cache.key(&sources, self.separable)
+        });
+
+        let cached = cache.zip(key).and_then(|(cache, key)| {
+            let binary = cache.load(key)?;
+            let shader =
+                Shader::from_binary(self.gl.clone(), &binary, stages.clone(), self.separable);
+            cache.loaded(key, shader.is_some());
+            shader
+        });
+
+        let shader = match cached {
+            Some(shader) => shader,
+            None => {
+                let shaders = self.compile_stages()?;
+                let shader = Shader::link(
+                    self.gl.clone(),
+                    &shaders,
+                    stages,
+                    self.separable,
+                    cache.is_some(),
+                )?;
+                if let Some((cache, key)) = cache.zip(key)
+                    && let Some(binary) = unsafe { self.gl.context().get_program_binary(shader.id) }
+                {
+                    cache.store(key, &binary);
+                }
+                shader
+            }
+        };
+
+        if let Some(label) = &self.label {
+            shader.set_label(label);
+        }
+
+        Ok(shader)
+    }
+
+    fn compile_stages(&self) -> Result<Vec<glow::Shader>> {
+        let mut shaders = Vec::with_capacity(self.stages.len());
+        for stage in &self.stages {
+            match compile(&self.gl, stage) {
+                Ok(shader) => shaders.push(shader),
+                Err(error) => {
+                    unsafe {
+                        shaders
+                            .iter()
+                            .for_each(|&shader| self.gl.context().delete_shader(shader))
+                    };
+                    return Err(error);
+                }
+            }
+        }
+
+        Ok(shaders)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let has = |stage| self.stages.iter().any(|source| source.stage == stage);
+
+        if self.stages.is_empty() {
+            return Err(anyhow::anyhow!("Program has no shader stage"));
+        }
+        for (index, source) in self.stages.iter().enumerate() {
+            if self.stages[..index]
+                .iter()
+                .any(|other| other.stage == source.stage)
+            {
+                return Err(anyhow::anyhow!(
+                    "{} shader stage given twice",
+                    source.stage.name()
+                ));
+            }
+        }
+        if has(ShaderStage::Compute) && self.stages.len() > 1 {
+            return Err(anyhow::anyhow!(
+                "Compute shader cannot be linked with other stages"
+            ));
+        }
+        if has(ShaderStage::TessControl) && !has(ShaderStage::TessEvaluation) {
+            return Err(anyhow::anyhow!(
+                "Tessellation control shader needs a tessellation evaluation shader"
+            ));
+        }
+        // Separable programs may leave the vertex stage to another program of the pipeline
+        if !self.separable && !has(ShaderStage::Compute) && !has(ShaderStage::Vertex) {
+            return Err(anyhow::anyhow!("Program has no vertex shader"));
+        }
+
+        Ok(())
+    }
+}
+
 pub struct Shader {
     pub id: glow::Program,
-    gl: Arc<glow::Context>,
+    stages: Vec<ShaderStage>,
+    separable: bool,
+    gl: Rc<GlState>,
 }
 
 impl Shader {
-    pub fn new(gl: Arc<glow::Context>, vertex_source: &str, fragment_source: &str) -> Result<Self> {
+    pub fn new(gl: Rc<GlState>, vertex_source: &str, fragment_source: &str) -> Result<Self> {
+        ShaderProgramBuilder::new(gl)
+            .stage(ShaderStage::Vertex, vertex_source)
+            .stage(ShaderStage::Fragment, fragment_source)
+            .build()
+    }
+
+    ///
+    /// Program made of a single compute shader, usually used through `ComputeShader`
+    ///
+    pub fn compute(gl: Rc<GlState>, source: &str) -> Result<Self> {
+        ShaderProgramBuilder::new(gl)
+            .stage(ShaderStage::Compute, source)
+            .build()
+    }
+
+    ///
+    /// Links compiled shaders into a program, the shaders are deleted afterwards
+    ///
+    /// `retrievable` asks the driver to keep the binary of the program for `ProgramCache`.
+    ///
+    fn link(
+        gl: Rc<GlState>,
+        shaders: &[glow::Shader],
+        stages: Vec<ShaderStage>,
+        separable: bool,
+        retrievable: bool,
+    ) -> Result<Self> {
         unsafe {
-            let vertex_shader = gl
-                .create_shader(glow::VERTEX_SHADER)
-                .or_else(|s| Err(anyhow::anyhow!(s)))?;
-            gl.shader_source(vertex_shader, vertex_source);
-            gl.compile_shader(vertex_shader);
-            if !gl.get_shader_compile_status(vertex_shader) {
-                let log = gl.get_shader_info_log(vertex_shader);
-                return Err(anyhow::anyhow!("Vertex shader compile error: {}", log));
-            }
+            let program = gl
+                .context()
+                .create_program()
+                .or_else(|s| Err(anyhow::anyhow!(s)));
+            let program = match program {
+                Ok(program) => program,
+                Err(error) => {
+                    shaders
+                        .iter()
+                        .for_each(|&shader| gl.context().delete_shader(shader));
+                    return Err(error);
+                }
+            };
 
-            let fragment_shader = gl
-                .create_shader(glow::FRAGMENT_SHADER)
-                .or_else(|s| Err(anyhow::anyhow!(s)))?;
-            gl.shader_source(fragment_shader, fragment_source);
-            gl.compile_shader(fragment_shader);
-            if !gl.get_shader_compile_status(fragment_shader) {
-                let log = gl.get_shader_info_log(fragment_shader);
-                return Err(anyhow::anyhow!("Fragment shader compile error: {}", log));
+            if separable {
+                match gl.pipeline_fns() {
+                    Ok(fns) => fns.program_parameter_i32(program, glow::PROGRAM_SEPARABLE, 1),
+                    Err(error) => {
+                        shaders
+                            .iter()
+                            .for_each(|&shader| gl.context().delete_shader(shader));
+                        gl.context().delete_program(program);
+                        return Err(error);
+                    }
+                }
             }
 
-            let program = gl.create_program().or_else(|s| Err(anyhow::anyhow!(s)))?;
-            gl.attach_shader(program, vertex_shader);
-            gl.attach_shader(program, fragment_shader);
-            gl.link_program(program);
-            if !gl.get_program_link_status(program) {
-                let log = gl.get_program_info_log(program);
-                return Err(anyhow::anyhow!("Program link error: {}", log));
+            if retrievable {
+                gl.context().program_binary_retrievable_hint(program, true);
             }
 
-            info!(
-                "Compile shaders {vertex_shader:?} and {fragment_shader:?}, and linked program {program:?}"
-            );
+            for &shader in shaders {
+                gl.context().attach_shader(program, shader);
+            }
+            gl.context().link_program(program);
 
             // We can delete shaders after linking
-            gl.delete_shader(vertex_shader);
-            gl.delete_shader(fragment_shader);
+            for &shader in shaders {
+                gl.context().detach_shader(program, shader);
+                gl.context().delete_shader(shader);
+            }
 
-            Ok(Self { id: program, gl })
+            if !gl.context().get_program_link_status(program) {
+                let log = gl.context().get_program_info_log(program);
+                gl.context().delete_program(program);
+                let names: Vec<&str> = stages.iter().map(|stage| stage.name()).collect();
+                return Err(anyhow::anyhow!(
+                    "Program link error with stages {names:?}: {log}"
+                ));
+            }
+
+            info!("Compiled shaders {shaders:?}, and linked program {program:?}");
+
+            Ok(Self {
+                id: program,
+                stages,
+                separable,
+                gl,
+            })
         }
     }
 
+    ///
+    /// Program created from a cached binary, `None` when the driver refuses it
+    ///
+    fn from_binary(
+        gl: Rc<GlState>,
+        binary: &ProgramBinary,
+        stages: Vec<ShaderStage>,
+        separable: bool,
+    ) -> Option<Self> {
+        unsafe {
+            let program = gl.context().create_program().ok()?;
+            if separable {
+                let Ok(fns) = gl.pipeline_fns() else {
+                    gl.context().delete_program(program);
+                    return None;
+                };
+                fns.program_parameter_i32(program, glow::PROGRAM_SEPARABLE, 1);
+            }
+
+            gl.context().program_binary(program, binary);
+            if !gl.context().get_program_link_status(program) {
+                gl.context().delete_program(program);
+                return None;
+            }
+
+            info!("Loaded program {program:?} from a cached binary");
+
+            Some(Self {
+                id: program,
+                stages,
+                separable,
+                gl,
+            })
+        }
+    }
+
+    pub fn stages(&self) -> &[ShaderStage] {
+        &self.stages
+    }
+
+    pub fn is_separable(&self) -> bool {
+        self.separable
+    }
+
+    ///
+    /// Names this program in GL debug messages
+    ///
+    pub fn set_label(&self, label: &str) {
+        debug::set_label(&self.gl, glow::PROGRAM, self.id.0.get(), label);
+    }
+
     pub fn get_loc(&self, name: &str) -> Result<UniformLocation> {
         unsafe {
-            let loc = self.gl.get_uniform_location(self.id, name);
+            let loc = self.gl.context().get_uniform_location(self.id, name);
             let Some(loc) = loc else {
                 return Err(anyhow::anyhow!(
                     "Uniform '{name}' not found in program {:?}",
@@ -68,27 +474,34 @@ impl Shader {
         }
     }
 
+    pub(crate) fn gl(&self) -> &Rc<GlState> {
+        &self.gl
+    }
+
     pub fn use_program(&self) {
-        unsafe {
-            self.gl.use_program(Some(self.id));
-        }
+        self.gl.set_program(Some(self.id));
     }
 
     pub fn uniform_1i(&self, location: &UniformLocation, v0: i32) {
         unsafe {
-            self.gl.uniform_1_i32(Some(location), v0);
+            self.gl
+                .context()
+                .program_uniform_1_i32(self.id, Some(location), v0);
         }
     }
 
     pub fn uniform_1f(&self, location: &UniformLocation, v0: f32) {
         unsafe {
-            self.gl.uniform_1_f32(Some(location), v0);
+            self.gl
+                .context()
+                .program_uniform_1_f32(self.id, Some(location), v0);
         }
     }
 
     pub fn uniform_2f_slice(&self, location: &UniformLocation, v0: &[Vec2]) {
         unsafe {
-            self.gl.uniform_2_f32_slice(
+            self.gl.context().program_uniform_2_f32_slice(
+                self.id,
                 Some(location),
                 &v0.iter()
                     .flat_map(|v| v.as_array().iter().cloned())
@@ -96,4 +509,23 @@ impl Shader {
             );
         }
     }
+
+    pub fn uniform_3f_slice(&self, location: &UniformLocation, v0: &[[f32; 3]]) {
+        unsafe {
+            self.gl.context().program_uniform_3_f32_slice(
+                self.id,
+                Some(location),
+                v0.as_flattened(),
+            );
+        }
+    }
+}
+
+impl Drop for Shader {
+    fn drop(&mut self) {
+        self.gl.forget_program(self.id);
+        unsafe {
+            self.gl.context().delete_program(self.id);
+        }
+    }
 }
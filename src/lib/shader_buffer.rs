@@ -0,0 +1,515 @@
+use std::{marker::PhantomData, rc::Rc};
+
+use anyhow::Result;
+use bytemuck::{Pod, Zeroable, bytes_of, cast_slice, cast_slice_mut};
+use glow::{COPY_READ_BUFFER, DYNAMIC_DRAW, HasContext, SHADER_STORAGE_BUFFER, UNIFORM_BUFFER};
+use log::info;
+
+use crate::{debug, gl_state::GlState};
+
+/// Binding point of the `Frame` uniform block updated by the runtime every frame
+pub const FRAME_BINDING: u32 = 0;
+
+///
+/// Per frame values bound to `FRAME_BINDING` for every shader, declared in GLSL as
+///
+/// ```glsl
+/// layout(std140, binding = 0) uniform Frame {
+///     vec2 Resolution;
+///     vec2 Mouse;
+///     float Time;
+///     float DeltaTime;
+///     uint FrameIndex;
+/// };
+/// ```
+///
+/// `Resolution` and `Mouse` are in pixels, with the origin at the bottom left like `gl_FragCoord`,
+/// and times are in seconds.
+///
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+pub struct FrameUniforms {
+    pub resolution: [f32; 2],
+    pub mouse: [f32; 2],
+    pub time: f32,
+    pub delta_time: f32,
+    pub frame_index: u32,
+}
+
+crate::impl_block!(FrameUniforms {
+    resolution: [f32; 2],
+    mouse: [f32; 2],
+    time: f32,
+    delta_time: f32,
+    frame_index: u32,
+});
+
+///
+/// Alignment and size of a GLSL type in one of the block layouts
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub align: usize,
+    pub size: usize,
+    /// Whether the Rust type has the same layout
+    pub valid: bool,
+}
+
+const fn round_up(value: usize, align: usize) -> usize {
+    value.div_ceil(align) * align
+}
+
+impl Layout {
+    pub const fn scalar() -> Self {
+        Self {
+            align: 4,
+            size: 4,
+            valid: true,
+        }
+    }
+
+    pub const fn vector(count: usize) -> Self {
+        Self {
+            align: if count == 2 { 8 } else { 16 },
+            size: 4 * count,
+            valid: true,
+        }
+    }
+
+    ///
+    /// Array of `count` elements, std140 rounds the element alignment up to 16 bytes
+    ///
+    pub const fn array(element: Layout, count: usize, rust_stride: usize, std140: bool) -> Self {
+        let align = if std140 {
+            round_up(element.align, 16)
+        } else {
+            element.align
+        };
+        let stride = round_up(element.size, align);
+
+        Self {
+            align,
+            size: stride * count,
+            valid: element.valid && stride == rust_stride,
+        }
+    }
+
+    ///
+    /// Start of a struct, members are added with `field`
+    ///
+    pub const fn block() -> Self {
+        Self {
+            align: 4,
+            size: 0,
+            valid: true,
+        }
+    }
+
+    ///
+    /// Places a member after the previous ones and checks it against the Rust field
+    ///
+    pub const fn field(self, member: Layout, rust_offset: usize, rust_size: usize) -> Self {
+        let offset = round_up(self.size, member.align);
+
+        Self {
+            align: if member.align > self.align {
+                member.align
+            } else {
+                self.align
+            },
+            size: offset + member.size,
+            valid: self.valid && member.valid && offset == rust_offset && member.size == rust_size,
+        }
+    }
+
+    ///
+    /// Ends a struct, its size is padded to its alignment which std140 rounds up to 16 bytes
+    ///
+    pub const fn finish(self, std140: bool) -> Self {
+        let align = if std140 {
+            round_up(self.align, 16)
+        } else {
+            self.align
+        };
+
+        Self {
+            align,
+            size: round_up(self.size, align),
+            valid: self.valid,
+        }
+    }
+}
+
+///
+/// Rust types whose std140 and std430 layouts are known
+///
+/// Implemented for `f32`, `i32`, `u32`, and arrays of them. Arrays of 2 to 4 scalars
+/// are vectors (`[f32; 3]` is a `vec3`), any other array is a GLSL array, so `[[f32; 4]; 4]`
+/// matches a `mat4`. Structs implement it with `impl_block!`.
+///
+pub trait BlockLayout {
+    const IS_SCALAR: bool = false;
+    const STD140: Layout;
+    const STD430: Layout;
+}
+
+macro_rules! impl_scalar_layout {
+    ($($scalar:ty),*) => {
+        $(
+            impl BlockLayout for $scalar {
+                const IS_SCALAR: bool = true;
+                const STD140: Layout = Layout::scalar();
+                const STD430: Layout = Layout::scalar();
+            }
+        )*
+    };
+}
+
+impl_scalar_layout!(f32, i32, u32);
+
+impl<T: BlockLayout, const N: usize> BlockLayout for [T; N] {
+    const STD140: Layout = if T::IS_SCALAR && N >= 2 && N <= 4 {
+        Layout::vector(N)
+    } else {
+        Layout::array(T::STD140, N, size_of::<T>(), true)
+    };
+    const STD430: Layout = if T::IS_SCALAR && N >= 2 && N <= 4 {
+        Layout::vector(N)
+    } else {
+        Layout::array(T::STD430, N, size_of::<T>(), false)
+    };
+}
+
+///
+/// Implements `BlockLayout` for a `#[repr(C)]` struct from its fields
+///
+/// Every field must be listed with its type, padding fields excepted. A struct that
+/// does not match the layout fails to compile when used in a buffer of that layout.
+///
+/// ```ignore
+/// #[repr(C)]
+/// #[derive(Clone, Copy, Pod, Zeroable)]
+/// struct Light {
+///     position: [f32; 3],
+///     intensity: f32,
+///     color: [f32; 4],
+/// }
+///
+/// impl_block!(Light {
+///     position: [f32; 3],
+///     intensity: f32,
+///     color: [f32; 4],
+/// });
+/// ```
+///
+#[macro_export]
+macro_rules! impl_block {
+    ($type:ty { $($field:ident: $field_type:ty),* $(,)? }) => {
+        impl $crate::shader_buffer::BlockLayout for $type {
+            const STD140: $crate::shader_buffer::Layout = $crate::shader_buffer::Layout::block()
+                $(.field(
+                    <$field_type as $crate::shader_buffer::BlockLayout>::STD140,
+                    ::std::mem::offset_of!($type, $field),
+                    ::std::mem::size_of::<$field_type>(),
+                ))*
+                .finish(true);
+            const STD430: $crate::shader_buffer::Layout = $crate::shader_buffer::Layout::block()
+                $(.field(
+                    <$field_type as $crate::shader_buffer::BlockLayout>::STD430,
+                    ::std::mem::offset_of!($type, $field),
+                    ::std::mem::size_of::<$field_type>(),
+                ))*
+                .finish(false);
+        }
+
+        // Checks the listed types against the actual fields
+        const _: fn(&$type) = |block| {
+            $(let _: &$field_type = &block.$field;)*
+        };
+    };
+}
+
+///
+/// Single std140 value read by shaders through a uniform block
+///
+pub struct UniformBuffer<T> {
+    pub id: glow::Buffer,
+    gl: Rc<GlState>,
+    value: PhantomData<T>,
+}
+
+impl<T: BlockLayout + Pod> UniformBuffer<T> {
+    pub fn new(gl: Rc<GlState>, value: &T) -> Result<Self> {
+        const {
+            assert!(
+                T::STD140.valid,
+                "Uniform buffer type does not match the std140 layout"
+            );
+        }
+
+        let id;
+        unsafe {
+            id = gl
+                .context()
+                .create_named_buffer()
+                .or_else(|s| Err(anyhow::anyhow!(s)))?;
+            // The block may be larger than the struct because of its trailing padding
+            let size = size_of::<T>().max(T::STD140.size);
+            gl.context()
+                .named_buffer_data_size(id, size as i32, DYNAMIC_DRAW);
+            gl.context()
+                .named_buffer_sub_data_u8_slice(id, 0, bytes_of(value));
+        }
+
+        info!("Initialized uniform buffer {id:?}");
+
+        Ok(Self {
+            id,
+            gl,
+            value: PhantomData,
+        })
+    }
+
+    pub fn update(&self, value: &T) {
+        unsafe {
+            self.gl
+                .context()
+                .named_buffer_sub_data_u8_slice(self.id, 0, bytes_of(value));
+        }
+    }
+
+    ///
+    /// Binds the buffer to the uniform block declared with `layout(binding = binding)`
+    ///
+    pub fn bind(&self, binding: u32) {
+        unsafe {
+            self.gl
+                .context()
+                .bind_buffer_base(UNIFORM_BUFFER, binding, Some(self.id));
+        }
+    }
+
+    ///
+    /// Names this buffer in GL debug messages
+    ///
+    pub fn set_label(&self, label: &str) {
+        debug::set_label(&self.gl, glow::BUFFER, self.id.0.get(), label);
+    }
+}
+
+impl<T> Drop for UniformBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.context().delete_buffer(self.id);
+        }
+    }
+}
+
+///
+/// Array of std430 elements that shaders can read and write through a storage block
+///
+pub struct StorageBuffer<T> {
+    pub id: glow::Buffer,
+    len: usize,
+    gl: Rc<GlState>,
+    elements: PhantomData<T>,
+}
+
+impl<T: BlockLayout + Pod> StorageBuffer<T> {
+    pub fn new(gl: Rc<GlState>, data: &[T]) -> Result<Self> {
+        const {
+            assert!(
+                T::STD430.valid && T::STD430.size == size_of::<T>(),
+                "Storage buffer element type does not match the std430 layout"
+            );
+        }
+
+        let id;
+        unsafe {
+            id = gl
+                .context()
+                .create_named_buffer()
+                .or_else(|s| Err(anyhow::anyhow!(s)))?;
+            gl.context()
+                .named_buffer_data_u8_slice(id, cast_slice(data), DYNAMIC_DRAW);
+        }
+
+        info!(
+            "Initialized storage buffer {id:?} of {} elements",
+            data.len()
+        );
+
+        Ok(Self {
+            id,
+            len: data.len(),
+            gl,
+            elements: PhantomData,
+        })
+    }
+
+    ///
+    /// Buffer of `len` zeroed elements, usually filled by a compute shader
+    ///
+    pub fn zeroed(gl: Rc<GlState>, len: usize) -> Result<Self> {
+        Self::new(gl, &vec![T::zeroed(); len])
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    ///
+    /// Overwrites elements starting at element `first`
+    ///
+    pub fn update(&self, first: usize, data: &[T]) -> Result<()> {
+        if first + data.len() > self.len {
+            return Err(anyhow::anyhow!(
+                "Update of {} elements at {first} overflows storage buffer {:?} of {} elements",
+                data.len(),
+                self.id,
+                self.len
+            ));
+        }
+
+        unsafe {
+            self.gl.context().named_buffer_sub_data_u8_slice(
+                self.id,
+                (first * size_of::<T>()) as i32,
+                cast_slice(data),
+            );
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Reads the elements back, waiting for the GPU to be done writing them
+    ///
+    /// Writes from shaders need a `Barrier::BUFFER_UPDATE` before this.
+    ///
+    pub fn read(&self) -> Vec<T> {
+        let mut data = vec![T::zeroed(); self.len];
+        unsafe {
+            self.gl
+                .context()
+                .bind_buffer(COPY_READ_BUFFER, Some(self.id));
+            self.gl
+                .context()
+                .get_buffer_sub_data(COPY_READ_BUFFER, 0, cast_slice_mut(&mut data));
+            self.gl.context().bind_buffer(COPY_READ_BUFFER, None);
+        }
+        data
+    }
+
+    ///
+    /// Binds the buffer to the storage block declared with `layout(binding = binding)`
+    ///
+    pub fn bind(&self, binding: u32) {
+        unsafe {
+            self.gl
+                .context()
+                .bind_buffer_base(SHADER_STORAGE_BUFFER, binding, Some(self.id));
+        }
+    }
+
+    ///
+    /// Names this buffer in GL debug messages
+    ///
+    pub fn set_label(&self, label: &str) {
+        debug::set_label(&self.gl, glow::BUFFER, self.id.0.get(), label);
+    }
+}
+
+impl<T> Drop for StorageBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.context().delete_buffer(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C)]
+    struct Light {
+        position: [f32; 3],
+        intensity: f32,
+        color: [f32; 4],
+    }
+
+    crate::impl_block!(Light {
+        position: [f32; 3],
+        intensity: f32,
+        color: [f32; 4],
+    });
+
+    /// `vec3` padded to 16 bytes by hand, so that arrays of it have the std140 stride
+    #[repr(C)]
+    struct PaddedVec3 {
+        value: [f32; 3],
+        _padding: f32,
+    }
+
+    crate::impl_block!(PaddedVec3 { value: [f32; 3] });
+
+    /// Rust puts `direction` right after `scale`, GLSL aligns it to 16 bytes
+    #[repr(C)]
+    struct Misaligned {
+        scale: f32,
+        direction: [f32; 3],
+    }
+
+    crate::impl_block!(Misaligned {
+        scale: f32,
+        direction: [f32; 3],
+    });
+
+    const fn layout(align: usize, size: usize, valid: bool) -> Layout {
+        Layout { align, size, valid }
+    }
+
+    #[test]
+    fn float_fills_vec3_padding() {
+        assert_eq!(Light::STD140, layout(16, 32, true));
+        assert_eq!(Light::STD430, layout(16, 32, true));
+    }
+
+    #[test]
+    fn scalar_arrays_have_std140_stride_of_16() {
+        let stride = size_of::<f32>();
+        assert_eq!(
+            Layout::array(Layout::scalar(), 3, stride, true),
+            layout(16, 48, false)
+        );
+        assert_eq!(
+            Layout::array(Layout::scalar(), 3, stride, false),
+            layout(4, 12, true)
+        );
+    }
+
+    #[test]
+    fn vec3_arrays_need_padding() {
+        assert_eq!(<[[f32; 3]; 2]>::STD140, layout(16, 32, false));
+        assert_eq!(<[[f32; 3]; 2]>::STD430, layout(16, 32, false));
+        assert_eq!(<[PaddedVec3; 2]>::STD140, layout(16, 32, true));
+        assert_eq!(<[PaddedVec3; 2]>::STD430, layout(16, 32, true));
+    }
+
+    #[test]
+    fn mat4_is_four_vec4() {
+        assert_eq!(<[[f32; 4]; 4]>::STD140, layout(16, 64, true));
+        assert_eq!(<[[f32; 4]; 4]>::STD430, layout(16, 64, true));
+    }
+
+    #[test]
+    fn misaligned_struct_is_rejected() {
+        assert_eq!(Misaligned::STD140, layout(16, 32, false));
+        assert_eq!(Misaligned::STD430, layout(16, 32, false));
+    }
+}
@@ -0,0 +1,305 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use anyhow::{Context, Result};
+use glow::UniformLocation;
+use log::info;
+
+use crate::shader::{Shader, ShaderProgramBuilder};
+
+#[derive(Clone, Debug)]
+enum Keyword {
+    /// Defined to 1 when enabled, left undefined otherwise
+    Toggle(String),
+    /// Defined to one of the values, each value being defined to its index
+    Choice(String, Vec<String>),
+}
+
+impl Keyword {
+    fn name(&self) -> &str {
+        match self {
+            Keyword::Toggle(name) | Keyword::Choice(name, _) => name,
+        }
+    }
+
+    fn count(&self) -> u32 {
+        match self {
+            Keyword::Toggle(_) => 2,
+            Keyword::Choice(_, values) => values.len() as u32,
+        }
+    }
+}
+
+///
+/// Value of every keyword of a `ShaderVariants`, in declaration order
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct VariantKey(Vec<u32>);
+
+struct Variant {
+    shader: Rc<Shader>,
+    /// Locations of the declared uniforms the variant kept, resolved once when compiled
+    locations: HashMap<String, UniformLocation>,
+}
+
+///
+/// Permutations of a program selected by keywords turned into `#define`s
+///
+/// Toggles are off and choices on their first value until set. Each variant is compiled
+/// the first time it is used, or ahead of time by `warm_up`, then kept, so switching
+/// variants between frames costs nothing once every used variant is compiled.
+///
+/// ```ignore
+/// let mut variants = ShaderVariants::new("pixelate", fullscreen_pass.shader_builder(source))
+///     .toggle("DITHER")
+///     .choice("PALETTE", &["PALETTE_16", "PALETTE_32"]);
+/// variants.set_toggle("DITHER", true)?;
+/// fullscreen_pass.draw(&variants.shader()?, target, viewport);
+/// ```
+///
+/// In GLSL, `#ifdef DITHER` and `#if PALETTE == PALETTE_32`.
+///
+/// Uniforms declared with `uniforms` are located once per variant, `location` then
+/// returns them without querying GL every frame.
+///
+pub struct ShaderVariants {
+    name: String,
+    template: ShaderProgramBuilder,
+    keywords: Vec<Keyword>,
+    uniforms: Vec<String>,
+    active: VariantKey,
+    variants: RefCell<HashMap<VariantKey, Variant>>,
+}
+
+impl ShaderVariants {
+    pub fn new(name: &str, template: ShaderProgramBuilder) -> Self {
+        Self {
+            name: name.to_owned(),
+            template,
+            keywords: Vec::new(),
+            uniforms: Vec::new(),
+            active: VariantKey::default(),
+            variants: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn toggle(mut self, name: &str) -> Self {
+        self.keywords.push(Keyword::Toggle(name.to_owned()));
+        self.active.0.push(0);
+        self
+    }
+
+    pub fn choice(mut self, name: &str, values: &[&str]) -> Self {
+        assert!(!values.is_empty(), "Keyword {name} has no value");
+        self.keywords.push(Keyword::Choice(
+            name.to_owned(),
+            values.iter().map(|value| value.to_string()).collect(),
+        ));
+        self.active.0.push(0);
+        self
+    }
+
+    ///
+    /// Uniforms to locate when a variant is compiled
+    ///
+    pub fn uniforms(mut self, names: &[&str]) -> Self {
+        self.uniforms
+            .extend(names.iter().map(|name| name.to_string()));
+        self
+    }
+
+    pub fn set_toggle(&mut self, name: &str, enabled: bool) -> Result<()> {
+        self.active = self.with_toggle(name, enabled)?;
+        Ok(())
+    }
+
+    ///
+    /// Key of the selected variant with one toggle changed, for passes picking the variant
+    /// when drawing
+    ///
+    pub fn with_toggle(&self, name: &str, enabled: bool) -> Result<VariantKey> {
+        let index = self.keyword(name)?;
+        let Keyword::Toggle(_) = &self.keywords[index] else {
+            return Err(anyhow::anyhow!(
+                "Keyword {name} of {} is not a toggle",
+                self.name
+            ));
+        };
+        let mut key = self.active.clone();
+        key.0[index] = enabled as u32;
+        Ok(key)
+    }
+
+    pub fn set_choice(&mut self, name: &str, value: &str) -> Result<()> {
+        let index = self.keyword(name)?;
+        let Keyword::Choice(_, values) = &self.keywords[index] else {
+            return Err(anyhow::anyhow!(
+                "Keyword {name} of {} is not a choice",
+                self.name
+            ));
+        };
+        let position = values
+            .iter()
+            .position(|candidate| candidate == value)
+            .with_context(|| format!("Keyword {name} of {} has no value {value}", self.name))?;
+        self.active.0[index] = position as u32;
+        Ok(())
+    }
+
+    ///
+    /// Key of the variant selected by the `set_*` calls
+    ///
+    pub fn key(&self) -> &VariantKey {
+        &self.active
+    }
+
+    ///
+    /// Selected variant, compiled if it is used for the first time
+    ///
+    pub fn shader(&self) -> Result<Rc<Shader>> {
+        self.variant(&self.active)
+    }
+
+    pub fn variant(&self, key: &VariantKey) -> Result<Rc<Shader>> {
+        if let Some(variant) = self.variants.borrow().get(key) {
+            return Ok(variant.shader.clone());
+        }
+
+        let description = self.describe(key)?;
+        let mut builder = self.template.clone();
+        for (name, value) in self.defines(key) {
+            builder = builder.define(&name, &value);
+        }
+        let shader = Rc::new(
+            builder
+                .label(&format!("{} {description}", self.name))
+                .build()
+                .with_context(|| {
+                    format!("Unable to build variant {description} of {}", self.name)
+                })?,
+        );
+
+        // Uniforms a variant does not use are optimized out and have no location
+        let locations = self
+            .uniforms
+            .iter()
+            .filter_map(|name| Some((name.clone(), shader.get_loc(name).ok()?)))
+            .collect();
+
+        info!("Compiled variant {description} of {}", self.name);
+
+        self.variants.borrow_mut().insert(
+            key.clone(),
+            Variant {
+                shader: shader.clone(),
+                locations,
+            },
+        );
+        Ok(shader)
+    }
+
+    ///
+    /// Location of a uniform declared with `uniforms` in the variant of `key`,
+    /// compiling the variant if needed
+    ///
+    pub fn location(&self, key: &VariantKey, name: &str) -> Result<UniformLocation> {
+        self.variant(key)?;
+        let variants = self.variants.borrow();
+        variants[key].locations.get(name).cloned().with_context(|| {
+            format!(
+                "Uniform {name} is not declared or not used by variant {} of {}",
+                self.describe(key).unwrap_or_default(),
+                self.name
+            )
+        })
+    }
+
+    ///
+    /// Every permutation of the keywords
+    ///
+    pub fn keys(&self) -> Vec<VariantKey> {
+        let mut keys = vec![VariantKey::default()];
+        for keyword in &self.keywords {
+            keys = keys
+                .into_iter()
+                .flat_map(|key| {
+                    (0..keyword.count()).map(move |value| {
+                        let mut key = key.clone();
+                        key.0.push(value);
+                        key
+                    })
+                })
+                .collect();
+        }
+        keys
+    }
+
+    ///
+    /// Compiles the given variants now instead of on their first use
+    ///
+    pub fn warm_up(&self, keys: &[VariantKey]) -> Result<()> {
+        for key in keys {
+            self.variant(key)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Number of variants compiled so far
+    ///
+    pub fn compiled(&self) -> usize {
+        self.variants.borrow().len()
+    }
+
+    fn keyword(&self, name: &str) -> Result<usize> {
+        self.keywords
+            .iter()
+            .position(|keyword| keyword.name() == name)
+            .with_context(|| format!("{} has no keyword {name}", self.name))
+    }
+
+    fn defines(&self, key: &VariantKey) -> Vec<(String, String)> {
+        let mut defines = Vec::new();
+        for (keyword, &value) in self.keywords.iter().zip(&key.0) {
+            match keyword {
+                Keyword::Toggle(name) => {
+                    if value != 0 {
+                        defines.push((name.clone(), "1".to_owned()));
+                    }
+                }
+                Keyword::Choice(name, values) => {
+                    for (index, option) in values.iter().enumerate() {
+                        defines.push((option.clone(), index.to_string()));
+                    }
+                    defines.push((name.clone(), values[value as usize].clone()));
+                }
+            }
+        }
+        defines
+    }
+
+    ///
+    /// Readable form of a key like `[DITHER PALETTE=PALETTE_32]`, failing on invalid keys
+    ///
+    fn describe(&self, key: &VariantKey) -> Result<String> {
+        if key.0.len() != self.keywords.len()
+            || self
+                .keywords
+                .iter()
+                .zip(&key.0)
+                .any(|(keyword, &value)| value >= keyword.count())
+        {
+            return Err(anyhow::anyhow!("Invalid variant {key:?} of {}", self.name));
+        }
+
+        let parts: Vec<String> = self
+            .keywords
+            .iter()
+            .zip(&key.0)
+            .filter_map(|(keyword, &value)| match keyword {
+                Keyword::Toggle(name) => (value != 0).then(|| name.clone()),
+                Keyword::Choice(name, values) => Some(format!("{name}={}", values[value as usize])),
+            })
+            .collect();
+        Ok(format!("[{}]", parts.join(" ")))
+    }
+}
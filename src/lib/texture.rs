@@ -1,57 +1,65 @@
-use std::sync::Arc;
+use std::rc::Rc;
 
 use anyhow::Result;
 use glow::{
-    CLAMP_TO_EDGE, HasContext, NEAREST, TEXTURE_2D, TEXTURE_MAG_FILTER, TEXTURE_MIN_FILTER,
-    TEXTURE_WRAP_S, TEXTURE_WRAP_T,
+    CLAMP_TO_EDGE, HasContext, NEAREST, TEXTURE_2D, TEXTURE_2D_MULTISAMPLE, TEXTURE_MAG_FILTER,
+    TEXTURE_MIN_FILTER, TEXTURE_WRAP_S, TEXTURE_WRAP_T, TEXTURE0,
 };
 use log::info;
 
+use crate::{debug, gl_state::GlState};
+
 // TODO: create an enum for texture formats
 
 pub struct Texture {
     pub id: glow::Texture,
     format: u32,
-    gl: Arc<glow::Context>,
+    /// Samples per texel, 0 for a regular texture
+    samples: i32,
+    label: Option<String>,
+    gl: Rc<GlState>,
 }
 
 impl Texture {
-    pub fn new(gl: Arc<glow::Context>, width: i32, height: i32, format: u32) -> Result<Self> {
-        let id = unsafe {
-            gl.create_named_texture(TEXTURE_2D)
-                .or_else(|s| Err(anyhow::anyhow!(s)))?
-        };
-        unsafe {
-            gl.texture_storage_2d(id, 1, format, width, height);
-            gl.texture_parameter_i32(id, TEXTURE_MIN_FILTER, NEAREST as i32);
-            gl.texture_parameter_i32(id, TEXTURE_MAG_FILTER, NEAREST as i32);
-            gl.texture_parameter_i32(id, TEXTURE_WRAP_S, CLAMP_TO_EDGE as i32);
-            gl.texture_parameter_i32(id, TEXTURE_WRAP_T, CLAMP_TO_EDGE as i32);
-        }
+    pub fn new(gl: Rc<GlState>, width: i32, height: i32, format: u32) -> Result<Self> {
+        Self::multisample(gl, width, height, format, 0)
+    }
+
+    ///
+    /// Texture storing `samples` values per texel, sampled in shaders with `sampler2DMS`
+    ///
+    /// Less than 2 samples gives a regular texture.
+    ///
+    pub fn multisample(
+        gl: Rc<GlState>,
+        width: i32,
+        height: i32,
+        format: u32,
+        samples: i32,
+    ) -> Result<Self> {
+        let samples = if samples > 1 { samples } else { 0 };
+        let id = Self::create(&gl, width, height, format, samples)?;
 
         info!("Initialized texture {id:?}");
 
-        Ok(Self { id, format, gl })
+        Ok(Self {
+            id,
+            format,
+            samples,
+            label: None,
+            gl,
+        })
     }
 
     pub fn resize(&mut self, width: i32, height: i32) -> Result<()> {
+        self.gl.forget_texture(self.id);
         unsafe {
-            self.gl.delete_texture(self.id);
+            self.gl.context().delete_texture(self.id);
+        }
+        self.id = Self::create(&self.gl, width, height, self.format, self.samples)?;
 
-            self.id = self
-                .gl
-                .create_named_texture(TEXTURE_2D)
-                .or_else(|s| Err(anyhow::anyhow!(s)))?;
-            self.gl
-                .texture_storage_2d(self.id, 1, self.format, width, height);
-            self.gl
-                .texture_parameter_i32(self.id, TEXTURE_MIN_FILTER, NEAREST as i32);
-            self.gl
-                .texture_parameter_i32(self.id, TEXTURE_MAG_FILTER, NEAREST as i32);
-            self.gl
-                .texture_parameter_i32(self.id, TEXTURE_WRAP_S, CLAMP_TO_EDGE as i32);
-            self.gl
-                .texture_parameter_i32(self.id, TEXTURE_WRAP_T, CLAMP_TO_EDGE as i32);
+        if let Some(label) = &self.label {
+            debug::set_label(&self.gl, glow::TEXTURE, self.id.0.get(), label);
         }
 
         info!("Resized texture {:?}", self.id);
@@ -59,18 +67,110 @@ impl Texture {
         Ok(())
     }
 
+    pub fn samples(&self) -> i32 {
+        self.samples
+    }
+
+    pub fn is_multisampled(&self) -> bool {
+        self.samples > 1
+    }
+
+    ///
+    /// `TEXTURE_2D` or `TEXTURE_2D_MULTISAMPLE`
+    ///
+    pub fn target(&self) -> u32 {
+        if self.is_multisampled() {
+            TEXTURE_2D_MULTISAMPLE
+        } else {
+            TEXTURE_2D
+        }
+    }
+
+    ///
+    /// Names this texture in GL debug messages, the label is kept across resizes
+    ///
+    pub fn set_label(&mut self, label: &str) {
+        debug::set_label(&self.gl, glow::TEXTURE, self.id.0.get(), label);
+        self.label = Some(label.to_owned());
+    }
+
     pub fn activate_texture(&self, unit: u32) {
+        self.gl.set_texture(unit, self.target(), Some(self.id));
+    }
+
+    ///
+    /// Binds the texture to image `unit` for `imageLoad`, `imageStore` and atomics
+    ///
+    /// `access` is `READ_ONLY`, `WRITE_ONLY` or `READ_WRITE` and `format` must match
+    /// the format qualifier of the image uniform, usually the format of the texture.
+    ///
+    pub fn bind_image_texture(&self, unit: u32, access: u32, format: u32) {
+        unsafe {
+            self.gl
+                .context()
+                .bind_image_texture(unit, Some(self.id), 0, false, 0, access, format);
+        }
+    }
+
+    ///
+    /// Binds the texture to image `unit` with its own format
+    ///
+    pub fn bind_image(&self, unit: u32, access: u32) {
+        self.bind_image_texture(unit, access, self.format);
+    }
+
+    fn create(
+        gl: &GlState,
+        width: i32,
+        height: i32,
+        format: u32,
+        samples: i32,
+    ) -> Result<glow::Texture> {
         unsafe {
-            self.gl.active_texture(unit);
-            self.gl.bind_texture(TEXTURE_2D, Some(self.id));
+            if samples > 1 {
+                let id = gl
+                    .context()
+                    .create_named_texture(TEXTURE_2D_MULTISAMPLE)
+                    .or_else(|s| Err(anyhow::anyhow!(s)))?;
+                // Multisample textures have no filtering or wrapping state
+                gl.set_texture(TEXTURE0, TEXTURE_2D_MULTISAMPLE, Some(id));
+                gl.context().tex_storage_2d_multisample(
+                    TEXTURE_2D_MULTISAMPLE,
+                    samples,
+                    format,
+                    width,
+                    height,
+                    true,
+                );
+
+                return Ok(id);
+            }
+
+            let id = gl
+                .context()
+                .create_named_texture(TEXTURE_2D)
+                .or_else(|s| Err(anyhow::anyhow!(s)))?;
+            gl.context()
+                .texture_storage_2d(id, 1, format, width, height);
+            gl.context()
+                .texture_parameter_i32(id, TEXTURE_MIN_FILTER, NEAREST as i32);
+            gl.context()
+                .texture_parameter_i32(id, TEXTURE_MAG_FILTER, NEAREST as i32);
+            gl.context()
+                .texture_parameter_i32(id, TEXTURE_WRAP_S, CLAMP_TO_EDGE as i32);
+            gl.context()
+                .texture_parameter_i32(id, TEXTURE_WRAP_T, CLAMP_TO_EDGE as i32);
+
+            Ok(id)
         }
     }
 }
 
 impl Drop for Texture {
     fn drop(&mut self) {
+        self.gl.forget_texture(self.id);
         unsafe {
-            self.gl.delete_texture(self.id);
+            self.gl.context().delete_texture(self.id);
         }
     }
 }
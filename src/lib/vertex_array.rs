@@ -1,65 +1,298 @@
-use std::sync::Arc;
+use std::rc::Rc;
 
 use anyhow::Result;
-use glow::{FLOAT, HasContext};
+use glow::HasContext;
 use log::info;
 
-use super::vertex_buffer::VertexBuffer;
+use super::{
+    debug,
+    gl_state::GlState,
+    index_buffer::IndexBuffer,
+    vertex_buffer::VertexBuffer,
+    vertex_layout::{Vertex, VertexLayout},
+};
 
 pub struct VertexArray {
     pub id: glow::VertexArray,
-    vertex_buffer: VertexBuffer,
-    gl: Arc<glow::Context>,
+    vertex_buffers: Vec<(VertexBuffer, VertexLayout)>,
+    index_buffer: Option<IndexBuffer>,
+    gl: Rc<GlState>,
 }
 
 impl VertexArray {
-    pub fn new(gl: Arc<glow::Context>, vertex_buffer: VertexBuffer) -> Result<Self> {
+    ///
+    /// Vertex array reading every attribute from a single interleaved buffer
+    ///
+    pub fn new(
+        gl: Rc<GlState>,
+        vertex_buffer: VertexBuffer,
+        layout: VertexLayout,
+    ) -> Result<Self> {
+        Self::from_buffers(gl, vec![(vertex_buffer, layout)])
+    }
+
+    ///
+    /// Uploads `vertices` and lays them out from their `Vertex` implementation
+    ///
+    pub fn from_vertices<V: Vertex>(gl: Rc<GlState>, vertices: &[V]) -> Result<Self> {
+        let vertex_buffer = VertexBuffer::new(gl.clone(), vertices)?;
+        Self::new(gl, vertex_buffer, V::layout())
+    }
+
+    ///
+    /// Vertex array whose attributes are split across several buffers,
+    /// each buffer is bound to the binding index matching its position
+    ///
+    pub fn from_buffers(
+        gl: Rc<GlState>,
+        vertex_buffers: Vec<(VertexBuffer, VertexLayout)>,
+    ) -> Result<Self> {
         let id;
 
         unsafe {
             id = gl
+                .context()
                 .create_named_vertex_array()
                 .or_else(|s| Err(anyhow::anyhow!(s)))?;
-            gl.vertex_array_attrib_format_f32(id, 0, 3, FLOAT, false, 0);
-            gl.vertex_array_vertex_buffer(
-                id,
-                0,
-                Some(vertex_buffer.id),
-                0,
-                (size_of::<f32>() * 3) as i32,
-            );
-            gl.vertex_array_attrib_binding_f32(id, 0, 0);
-            gl.enable_vertex_array_attrib(id, 0);
+
+            for (binding, (vertex_buffer, layout)) in vertex_buffers.iter().enumerate() {
+                let binding = binding as u32;
+                gl.context().vertex_array_vertex_buffer(
+                    id,
+                    binding,
+                    Some(vertex_buffer.id),
+                    0,
+                    layout.stride as i32,
+                );
+                if layout.is_instanced() {
+                    // There is no direct state access version of the binding divisor in glow
+                    gl.set_vertex_array(Some(id));
+                    gl.context().vertex_binding_divisor(binding, layout.divisor);
+                }
+
+                for attribute in &layout.attributes {
+                    if attribute.is_integer() {
+                        gl.context().vertex_array_attrib_format_i32(
+                            id,
+                            attribute.location,
+                            attribute.count,
+                            attribute.kind.gl_type(),
+                            attribute.offset,
+                        );
+                    } else {
+                        gl.context().vertex_array_attrib_format_f32(
+                            id,
+                            attribute.location,
+                            attribute.count,
+                            attribute.kind.gl_type(),
+                            attribute.normalized,
+                            attribute.offset,
+                        );
+                    }
+                    gl.context().vertex_array_attrib_binding_f32(id, attribute.location, binding);
+                    gl.context().enable_vertex_array_attrib(id, attribute.location);
+                }
+            }
         }
 
         info!("Initialized vertex array {id:?}");
 
         Ok(Self {
             id,
-            vertex_buffer,
+            vertex_buffers,
+            index_buffer: None,
             gl,
         })
     }
 
-    pub fn bind(&self) {
+    ///
+    /// Number of whole vertices held by the smallest per vertex buffer
+    ///
+    pub fn vertex_count(&self) -> i32 {
+        self.vertex_buffers
+            .iter()
+            .filter(|(_, layout)| layout.stride > 0 && !layout.is_instanced())
+            .map(|(vertex_buffer, layout)| (vertex_buffer.size / layout.stride) as i32)
+            .min()
+            .unwrap_or(0)
+    }
+
+    ///
+    /// Number of instances the per instance buffers hold data for, 0 without any
+    ///
+    pub fn instance_count(&self) -> i32 {
+        self.vertex_buffers
+            .iter()
+            .filter(|(_, layout)| layout.stride > 0 && layout.is_instanced())
+            .map(|(vertex_buffer, layout)| {
+                (vertex_buffer.size / layout.stride) as i32 * layout.divisor as i32
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    ///
+    /// Buffer bound at `binding`, to update or stream its content
+    ///
+    pub fn vertex_buffer_mut(&mut self, binding: usize) -> Option<&mut VertexBuffer> {
+        self.vertex_buffers
+            .get_mut(binding)
+            .map(|(vertex_buffer, _)| vertex_buffer)
+    }
+
+    ///
+    /// Attaches the buffer used by the indexed draws, replacing the previous one
+    ///
+    pub fn set_index_buffer(&mut self, index_buffer: IndexBuffer) {
         unsafe {
-            self.gl.bind_vertex_array(Some(self.id));
+            self.gl
+                .context()
+                .vertex_array_element_buffer(self.id, Some(index_buffer.id));
+        }
+        self.index_buffer = Some(index_buffer);
+    }
+
+    pub fn index_buffer(&self) -> Option<&IndexBuffer> {
+        self.index_buffer.as_ref()
+    }
+
+    ///
+    /// Names this vertex array and its buffers in GL debug messages
+    ///
+    pub fn set_label(&self, label: &str) {
+        debug::set_label(&self.gl, glow::VERTEX_ARRAY, self.id.0.get(), label);
+        for (binding, (vertex_buffer, _)) in self.vertex_buffers.iter().enumerate() {
+            vertex_buffer.set_label(&format!("{label} vertices {binding}"));
+        }
+        if let Some(index_buffer) = &self.index_buffer {
+            index_buffer.set_label(&format!("{label} indices"));
         }
     }
 
+    pub fn bind(&self) {
+        self.gl.set_vertex_array(Some(self.id));
+    }
+
     pub fn draw(&self, mode: u32) {
         unsafe {
             self.bind();
             self.gl
-                .draw_arrays(mode, 0, self.vertex_buffer.vertex_count);
+                .context()
+                .draw_arrays(mode, 0, self.vertex_count());
+        }
+    }
+
+    ///
+    /// Draws `count` vertices starting at `first`
+    ///
+    pub fn draw_range(&self, mode: u32, first: i32, count: i32) {
+        unsafe {
+            self.bind();
+            self.gl.context().draw_arrays(mode, first, count);
+        }
+    }
+
+    ///
+    /// Draws every vertex `instance_count` times
+    ///
+    pub fn draw_instanced(&self, mode: u32, instance_count: i32) {
+        unsafe {
+            self.bind();
+            self.gl
+                .context()
+                .draw_arrays_instanced(mode, 0, self.vertex_count(), instance_count);
+        }
+    }
+
+    ///
+    /// Draws every index of the attached index buffer `instance_count` times
+    ///
+    pub fn draw_indexed_instanced(&self, mode: u32, instance_count: i32) -> Result<()> {
+        let index_buffer = self.get_index_buffer()?;
+        let index_type = index_buffer.index_type;
+
+        unsafe {
+            self.bind();
+            self.gl.context().draw_elements_instanced(
+                mode,
+                index_buffer.index_count,
+                index_type.gl_type(),
+                0,
+                instance_count,
+            );
         }
+
+        Ok(())
+    }
+
+    ///
+    /// Draws every index of the attached index buffer
+    ///
+    pub fn draw_indexed(&self, mode: u32) -> Result<()> {
+        let count = self.get_index_buffer()?.index_count;
+        self.draw_indexed_base_vertex(mode, 0, count, 0)
+    }
+
+    ///
+    /// Draws `count` indices starting at index `first`
+    ///
+    pub fn draw_indexed_range(&self, mode: u32, first: i32, count: i32) -> Result<()> {
+        self.draw_indexed_base_vertex(mode, first, count, 0)
+    }
+
+    ///
+    /// Draws `count` indices starting at index `first`, adding `base_vertex` to every index
+    ///
+    pub fn draw_indexed_base_vertex(
+        &self,
+        mode: u32,
+        first: i32,
+        count: i32,
+        base_vertex: i32,
+    ) -> Result<()> {
+        let index_buffer = self.get_index_buffer()?;
+        let index_type = index_buffer.index_type;
+        if first < 0 || count < 0 || first + count > index_buffer.index_count {
+            return Err(anyhow::anyhow!(
+                "Index range {first}..{} out of bounds for {} indices",
+                first + count,
+                index_buffer.index_count
+            ));
+        }
+
+        let offset = first * index_type.size() as i32;
+        unsafe {
+            self.bind();
+            if base_vertex == 0 {
+                self.gl
+                    .context()
+                    .draw_elements(mode, count, index_type.gl_type(), offset);
+            } else {
+                self.gl.context().draw_elements_base_vertex(
+                    mode,
+                    count,
+                    index_type.gl_type(),
+                    offset,
+                    base_vertex,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_index_buffer(&self) -> Result<&IndexBuffer> {
+        self.index_buffer
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Vertex array {:?} has no index buffer", self.id))
     }
 }
 
 impl Drop for VertexArray {
     fn drop(&mut self) {
         unsafe {
-            self.gl.delete_vertex_array(self.id);
+            self.gl.forget_vertex_array(self.id);
+            self.gl.context().delete_vertex_array(self.id);
         }
     }
 }
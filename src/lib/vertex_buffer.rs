@@ -1,39 +1,337 @@
-use std::sync::Arc;
+use std::rc::Rc;
 
 use anyhow::Result;
-use bytemuck::cast_slice;
-use glow::{HasContext, STATIC_DRAW};
-use log::info;
+use bytemuck::{Pod, cast_slice};
+use glow::{
+    ALREADY_SIGNALED, CONDITION_SATISFIED, COPY_WRITE_BUFFER, DYNAMIC_DRAW, HasContext,
+    MAP_COHERENT_BIT, MAP_PERSISTENT_BIT, MAP_WRITE_BIT, STATIC_DRAW, STREAM_DRAW,
+    SYNC_FLUSH_COMMANDS_BIT, SYNC_GPU_COMMANDS_COMPLETE, WAIT_FAILED,
+};
+use log::{info, warn};
+
+use crate::{debug, gl_state::GlState, vertex_layout::Vertex};
+
+/// Nanoseconds to wait on a fence before checking again
+const FENCE_TIMEOUT: i32 = 1_000_000;
+
+///
+/// How often the content of a buffer is expected to change
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferUsage {
+    /// Uploaded once, drawn many times
+    Static,
+    /// Updated occasionally, drawn many times
+    Dynamic,
+    /// Rewritten about every time it is drawn
+    Stream,
+}
+
+impl BufferUsage {
+    pub fn gl_usage(self) -> u32 {
+        match self {
+            BufferUsage::Static => STATIC_DRAW,
+            BufferUsage::Dynamic => DYNAMIC_DRAW,
+            BufferUsage::Stream => STREAM_DRAW,
+        }
+    }
+}
+
+///
+/// Persistently mapped storage split into sections that the CPU writes in turn,
+/// each section is fenced so it is only rewritten once the GPU is done reading it
+///
+struct PersistentMapping {
+    ptr: *mut u8,
+    section_size: usize,
+    fences: Vec<Option<glow::Fence>>,
+    section: usize,
+    cursor: usize,
+}
 
 pub struct VertexBuffer {
     pub id: glow::Buffer,
-    pub vertex_count: i32,
-    gl: Arc<glow::Context>,
+    /// Size of the buffer in bytes
+    pub size: usize,
+    usage: BufferUsage,
+    mapping: Option<PersistentMapping>,
+    gl: Rc<GlState>,
 }
 
 impl VertexBuffer {
-    pub fn new(gl: Arc<glow::Context>, vertices: &[f32]) -> Result<Self> {
+    ///
+    /// Static buffer of `vertices`, whose type fixes the element type of untyped literals
+    ///
+    pub fn new<T: Vertex>(gl: Rc<GlState>, vertices: &[T]) -> Result<Self> {
+        Self::with_usage(gl, vertices, BufferUsage::Static)
+    }
+
+    pub fn with_usage<T: Vertex>(
+        gl: Rc<GlState>,
+        vertices: &[T],
+        usage: BufferUsage,
+    ) -> Result<Self> {
         let id;
+        let data: &[u8] = cast_slice(vertices);
 
         unsafe {
-            id = gl.create_named_buffer().or_else(|s| Err(anyhow::anyhow!(s)))?;
-            gl.named_buffer_data_u8_slice(id, cast_slice(vertices), STATIC_DRAW);
+            id = gl
+                .context()
+                .create_named_buffer()
+                .or_else(|s| Err(anyhow::anyhow!(s)))?;
+            gl.context()
+                .named_buffer_data_u8_slice(id, data, usage.gl_usage());
         }
 
         info!("Initialized vertex buffer {id:?}");
 
         Ok(Self {
             id,
-            vertex_count: (vertices.len() / 3) as i32,
+            size: data.len(),
+            usage,
+            mapping: None,
+            gl,
+        })
+    }
+
+    ///
+    /// Ring buffer of `sections` parts of `section_size` bytes, persistently mapped for writing
+    ///
+    /// Each frame writes its data with `write` and calls `finish_frame` once the draws
+    /// using it are submitted. Usually three sections are enough to never wait on the GPU.
+    ///
+    pub fn streaming(gl: Rc<GlState>, section_size: usize, sections: usize) -> Result<Self> {
+        let size = section_size * sections;
+        let flags = MAP_WRITE_BIT | MAP_PERSISTENT_BIT | MAP_COHERENT_BIT;
+
+        let id;
+        let ptr;
+        unsafe {
+            id = gl
+                .context()
+                .create_named_buffer()
+                .or_else(|s| Err(anyhow::anyhow!(s)))?;
+            gl.context().bind_buffer(COPY_WRITE_BUFFER, Some(id));
+            gl.context()
+                .buffer_storage(COPY_WRITE_BUFFER, size as i32, None, flags);
+            ptr = gl
+                .context()
+                .map_buffer_range(COPY_WRITE_BUFFER, 0, size as i32, flags);
+            gl.context().bind_buffer(COPY_WRITE_BUFFER, None);
+        }
+
+        if ptr.is_null() {
+            unsafe {
+                gl.context().delete_buffer(id);
+            }
+            return Err(anyhow::anyhow!("Unable to map vertex buffer {id:?}"));
+        }
+
+        info!("Initialized streaming vertex buffer {id:?} with {sections} sections");
+
+        Ok(Self {
+            id,
+            size,
+            usage: BufferUsage::Stream,
+            mapping: Some(PersistentMapping {
+                ptr,
+                section_size,
+                fences: (0..sections).map(|_| None).collect(),
+                section: 0,
+                cursor: 0,
+            }),
             gl,
         })
     }
+
+    pub fn usage(&self) -> BufferUsage {
+        self.usage
+    }
+
+    ///
+    /// Overwrites part of the buffer, starting `offset` bytes in
+    ///
+    pub fn update<T: Pod>(&mut self, offset: usize, data: &[T]) -> Result<()> {
+        let data: &[u8] = cast_slice(data);
+        if offset + data.len() > self.size {
+            return Err(anyhow::anyhow!(
+                "Update of {} bytes at {offset} overflows vertex buffer {:?} of {} bytes",
+                data.len(),
+                self.id,
+                self.size
+            ));
+        }
+
+        if let Some(mapping) = &mut self.mapping {
+            // Every section the range touches may still be read by a previous frame
+            let first = offset / mapping.section_size;
+            let last = (offset + data.len()).saturating_sub(1) / mapping.section_size;
+            for section in first..=last.max(first) {
+                if let Some(fence) = mapping.fences[section].take() {
+                    wait_fence(&self.gl, fence, self.id)?;
+                }
+            }
+
+            unsafe {
+                std::ptr::copy_nonoverlapping(data.as_ptr(), mapping.ptr.add(offset), data.len());
+            }
+            return Ok(());
+        }
+
+        unsafe {
+            self.gl
+                .context()
+                .named_buffer_sub_data_u8_slice(self.id, offset as i32, data);
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Replaces the whole content, letting the driver allocate new storage
+    /// instead of waiting for draws that still read the old one
+    ///
+    pub fn orphan<T: Pod>(&mut self, data: &[T]) -> Result<()> {
+        if self.mapping.is_some() {
+            return Err(anyhow::anyhow!(
+                "Streaming vertex buffer {:?} cannot be orphaned",
+                self.id
+            ));
+        }
+
+        let data: &[u8] = cast_slice(data);
+        unsafe {
+            self.gl.context().named_buffer_data_size(
+                self.id,
+                data.len() as i32,
+                self.usage.gl_usage(),
+            );
+            self.gl
+                .context()
+                .named_buffer_sub_data_u8_slice(self.id, 0, data);
+        }
+        self.size = data.len();
+
+        Ok(())
+    }
+
+    ///
+    /// Appends data to the current section of a streaming buffer
+    ///
+    /// Returns the index of the first written element counted in `T`, which is the
+    /// `first` vertex to draw when the buffer holds vertices of type `T`.
+    ///
+    pub fn write<T: Pod>(&mut self, data: &[T]) -> Result<usize> {
+        let Some(mapping) = &mut self.mapping else {
+            return Err(anyhow::anyhow!(
+                "Vertex buffer {:?} is not a streaming buffer",
+                self.id
+            ));
+        };
+
+        let element_size = size_of::<T>().max(1);
+        let section_start = mapping.section * mapping.section_size;
+        let start = (section_start + mapping.cursor).next_multiple_of(element_size);
+        let bytes: &[u8] = cast_slice(data);
+
+        if start + bytes.len() > section_start + mapping.section_size {
+            return Err(anyhow::anyhow!(
+                "Write of {} bytes overflows the {} bytes sections of vertex buffer {:?}",
+                bytes.len(),
+                mapping.section_size,
+                self.id
+            ));
+        }
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), mapping.ptr.add(start), bytes.len());
+        }
+        mapping.cursor = start + bytes.len() - section_start;
+
+        Ok(start / element_size)
+    }
+
+    ///
+    /// Fences the section written this frame and moves to the next one,
+    /// waiting for the GPU if it is still reading it
+    ///
+    pub fn finish_frame(&mut self) -> Result<()> {
+        let Some(mapping) = &mut self.mapping else {
+            return Ok(());
+        };
+
+        unsafe {
+            let fence = self
+                .gl
+                .context()
+                .fence_sync(SYNC_GPU_COMMANDS_COMPLETE, 0)
+                .or_else(|s| Err(anyhow::anyhow!(s)))?;
+            if let Some(old) = mapping.fences[mapping.section].replace(fence) {
+                self.gl.context().delete_sync(old);
+            }
+
+            mapping.section = (mapping.section + 1) % mapping.fences.len();
+            mapping.cursor = 0;
+        }
+
+        if let Some(fence) = mapping.fences[mapping.section].take() {
+            wait_fence(&self.gl, fence, self.id)?;
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Names this buffer in GL debug messages
+    ///
+    pub fn set_label(&self, label: &str) {
+        debug::set_label(&self.gl, glow::BUFFER, self.id.0.get(), label);
+    }
+}
+
+///
+/// Blocks until the GPU signals `fence` and deletes it, warning when it had to wait
+///
+fn wait_fence(gl: &GlState, fence: glow::Fence, id: glow::Buffer) -> Result<()> {
+    let mut waited = false;
+    unsafe {
+        loop {
+            match gl
+                .context()
+                .client_wait_sync(fence, SYNC_FLUSH_COMMANDS_BIT, FENCE_TIMEOUT)
+            {
+                ALREADY_SIGNALED | CONDITION_SATISFIED => break,
+                WAIT_FAILED => {
+                    gl.context().delete_sync(fence);
+                    return Err(anyhow::anyhow!("Waiting on vertex buffer {id:?} failed"));
+                }
+                _ => waited = true,
+            }
+        }
+        gl.context().delete_sync(fence);
+    }
+
+    if waited {
+        warn!("Stalled on streaming vertex buffer {id:?}");
+    }
+
+    Ok(())
 }
 
 impl Drop for VertexBuffer {
     fn drop(&mut self) {
         unsafe {
-            self.gl.delete_buffer(self.id);
+            if let Some(mapping) = self.mapping.take() {
+                for fence in mapping.fences.into_iter().flatten() {
+                    self.gl.context().delete_sync(fence);
+                }
+                self.gl
+                    .context()
+                    .bind_buffer(COPY_WRITE_BUFFER, Some(self.id));
+                self.gl.context().unmap_buffer(COPY_WRITE_BUFFER);
+                self.gl.context().bind_buffer(COPY_WRITE_BUFFER, None);
+            }
+            self.gl.context().delete_buffer(self.id);
         }
     }
 }
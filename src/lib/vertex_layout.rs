@@ -0,0 +1,270 @@
+use bytemuck::Pod;
+use glow::{BYTE, FLOAT, HALF_FLOAT, INT, SHORT, UNSIGNED_BYTE, UNSIGNED_INT, UNSIGNED_SHORT};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttributeType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F16,
+    F32,
+}
+
+impl AttributeType {
+    pub fn gl_type(self) -> u32 {
+        match self {
+            AttributeType::I8 => BYTE,
+            AttributeType::U8 => UNSIGNED_BYTE,
+            AttributeType::I16 => SHORT,
+            AttributeType::U16 => UNSIGNED_SHORT,
+            AttributeType::I32 => INT,
+            AttributeType::U32 => UNSIGNED_INT,
+            AttributeType::F16 => HALF_FLOAT,
+            AttributeType::F32 => FLOAT,
+        }
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            AttributeType::I8 | AttributeType::U8 => 1,
+            AttributeType::I16 | AttributeType::U16 | AttributeType::F16 => 2,
+            AttributeType::I32 | AttributeType::U32 | AttributeType::F32 => 4,
+        }
+    }
+
+    pub fn is_integer(self) -> bool {
+        !matches!(self, AttributeType::F16 | AttributeType::F32)
+    }
+}
+
+///
+/// A single shader input read from a vertex buffer
+///
+/// Integer attributes that are not normalized reach the shader as integers (`ivec`/`uvec`),
+/// everything else is converted to floats.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexAttribute {
+    pub location: u32,
+    pub kind: AttributeType,
+    pub count: i32,
+    pub normalized: bool,
+    pub offset: u32,
+}
+
+impl VertexAttribute {
+    pub fn size(&self) -> usize {
+        self.kind.size() * self.count as usize
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.kind.is_integer() && !self.normalized
+    }
+}
+
+///
+/// Describes how the vertices of one buffer are laid out
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VertexLayout {
+    pub attributes: Vec<VertexAttribute>,
+    pub stride: usize,
+    /// Number of instances sharing an element, 0 for per vertex data
+    pub divisor: u32,
+}
+
+impl VertexLayout {
+    ///
+    /// Empty layout whose attributes are packed one after the other
+    ///
+    pub fn new() -> Self {
+        Self {
+            attributes: Vec::new(),
+            stride: 0,
+            divisor: 0,
+        }
+    }
+
+    ///
+    /// Empty layout for vertices of `stride` bytes, attributes are placed with `attribute_at`
+    ///
+    pub fn with_stride(stride: usize) -> Self {
+        Self {
+            attributes: Vec::new(),
+            stride,
+            divisor: 0,
+        }
+    }
+
+    ///
+    /// Appends an attribute right after the previous ones, growing the stride
+    ///
+    pub fn attribute(
+        mut self,
+        location: u32,
+        kind: AttributeType,
+        count: i32,
+        normalized: bool,
+    ) -> Self {
+        let attribute = VertexAttribute {
+            location,
+            kind,
+            count,
+            normalized,
+            offset: self.stride as u32,
+        };
+        self.stride += attribute.size();
+        self.attributes.push(attribute);
+        self
+    }
+
+    ///
+    /// Adds an attribute at an explicit offset, leaving the stride untouched
+    ///
+    pub fn attribute_at(
+        mut self,
+        location: u32,
+        offset: usize,
+        kind: AttributeType,
+        count: i32,
+        normalized: bool,
+    ) -> Self {
+        self.attributes.push(VertexAttribute {
+            location,
+            kind,
+            count,
+            normalized,
+            offset: offset as u32,
+        });
+        self
+    }
+
+    ///
+    /// Makes the buffer advance once every `divisor` instances instead of once per vertex
+    ///
+    pub fn per_instance(mut self, divisor: u32) -> Self {
+        self.divisor = divisor;
+        self
+    }
+
+    pub fn is_instanced(&self) -> bool {
+        self.divisor > 0
+    }
+
+    ///
+    /// Single `vec3` position at location 0, the layout of the engine quads
+    ///
+    pub fn position3() -> Self {
+        Self::new().attribute(0, AttributeType::F32, 3, false)
+    }
+}
+
+impl Default for VertexLayout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+///
+/// Rust types that map to a vertex attribute format
+///
+pub trait AttributeFormat {
+    const KIND: AttributeType;
+    const COUNT: i32;
+}
+
+macro_rules! impl_attribute_format {
+    ($($scalar:ty => $kind:ident),* $(,)?) => {
+        $(
+            impl AttributeFormat for $scalar {
+                const KIND: AttributeType = AttributeType::$kind;
+                const COUNT: i32 = 1;
+            }
+
+            impl<const N: usize> AttributeFormat for [$scalar; N] {
+                const KIND: AttributeType = AttributeType::$kind;
+                const COUNT: i32 = N as i32;
+            }
+        )*
+    };
+}
+
+impl_attribute_format!(
+    i8 => I8,
+    u8 => U8,
+    i16 => I16,
+    u16 => U16,
+    i32 => I32,
+    u32 => U32,
+    f32 => F32,
+);
+
+///
+/// A `#[repr(C)]` vertex type that describes its own layout
+///
+/// Usually implemented with the `impl_vertex!` macro.
+///
+pub trait Vertex: Pod {
+    fn layout() -> VertexLayout;
+}
+
+impl Vertex for [f32; 3] {
+    fn layout() -> VertexLayout {
+        VertexLayout::position3()
+    }
+}
+
+///
+/// Format of a struct field, used by `impl_vertex!` to infer attribute types
+///
+pub fn field_format<S, T: AttributeFormat>(_field: fn(&S) -> &T) -> (AttributeType, i32) {
+    (T::KIND, T::COUNT)
+}
+
+///
+/// Implements `Vertex` for a `#[repr(C)]` struct from its fields
+///
+/// ```ignore
+/// #[repr(C)]
+/// #[derive(Clone, Copy, Pod, Zeroable)]
+/// struct ColoredVertex {
+///     position: [f32; 3],
+///     color: [u8; 4],
+/// }
+///
+/// impl_vertex!(ColoredVertex {
+///     0 => position,
+///     1 => color (normalized),
+/// });
+/// ```
+///
+#[macro_export]
+macro_rules! impl_vertex {
+    (@normalized) => { false };
+    (@normalized normalized) => { true };
+    ($type:ty { $($location:literal => $field:ident $(($($flag:tt)*))?),* $(,)? }) => {
+        impl $crate::vertex_layout::Vertex for $type {
+            fn layout() -> $crate::vertex_layout::VertexLayout {
+                let layout = $crate::vertex_layout::VertexLayout::with_stride(
+                    ::std::mem::size_of::<$type>(),
+                );
+                $(
+                    let (kind, count) =
+                        $crate::vertex_layout::field_format(|vertex: &$type| &vertex.$field);
+                    let normalized = $crate::impl_vertex!(@normalized $($($flag)*)?);
+                    let layout = layout.attribute_at(
+                        $location,
+                        ::std::mem::offset_of!($type, $field),
+                        kind,
+                        count,
+                        normalized,
+                    );
+                )*
+                layout
+            }
+        }
+    };
+}
@@ -1,14 +1,78 @@
-use handler::AppHandler;
+use anyhow::Result;
+use clap::Parser;
+use cli::{Cli, Command};
+use handler::{AppHandler, HandlerConfig};
+use recorder::RecorderConfig;
 
-use seagull_lib::app::App;
+use seagull_lib::{
+    app::App,
+    clock::{Clock, ClockMode},
+    input::ActionMap,
+};
 
+mod cli;
+mod effect;
 mod handler;
 mod voronoi;
 mod pixelate;
+mod preset;
+mod recorder;
 
-fn main() {
+const ACTIONS_PATH: &str = "actions.toml";
+
+fn main() -> Result<()> {
     env_logger::init();
 
-    let mut app = App::new(|gl, window, size| Ok(Box::new(AppHandler::new(gl, window, size)?)));
-    app.run().unwrap();
+    let Cli { options, command } = Cli::parse();
+    if !options.effects.is_empty() {
+        effect::validate_chain(&options.effects)?;
+    }
+    let mut config = HandlerConfig {
+        seed: options.seed,
+        preset: options.preset,
+        chain: options.effects,
+        palette: options.palette,
+        render: None,
+    };
+    let mut time_step = None;
+    match command {
+        Some(Command::List) => {
+            cli::list_effects();
+            return Ok(());
+        }
+        Some(Command::Render(args)) => {
+            config.render = Some(RecorderConfig {
+                format: args.format,
+                resolution: args.resolution,
+                frames: Some(args.frames),
+                time_step: args.time_step,
+                output: args.output.unwrap_or_else(|| args.format.default_output()),
+            });
+            time_step = Some(args.time_step);
+        }
+        None => {}
+    }
+
+    let actions = if std::path::Path::new(ACTIONS_PATH).exists() {
+        ActionMap::load(ACTIONS_PATH)?
+    } else {
+        ActionMap::parse(include_str!("../actions.toml"))?
+    };
+
+    let mut app = App::new(move |context| AppHandler::new(context, config))
+        .with_action_map(actions)
+        .with_program_cache(std::env::temp_dir().join("seagull-program-cache"))
+        .with_fullscreen(options.fullscreen);
+    if let Some(size) = options.size {
+        app = app.with_window_size(size);
+    }
+    if let Some(vsync) = options.vsync {
+        app = app.with_vsync(vsync);
+    }
+    if let Some(time_step) = time_step {
+        app = app
+            .with_hidden_window()
+            .with_clock(Clock::new(ClockMode::FixedStep(time_step)));
+    }
+    app.run()
 }
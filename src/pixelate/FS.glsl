@@ -1,9 +1,20 @@
 #version 460
 
-uniform float Elapsed = 0.0;
+layout(std140, binding = 0) uniform Frame {
+    vec2 Resolution;
+    vec2 Mouse;
+    float Time;
+    float DeltaTime;
+    uint FrameIndex;
+};
 
 in vec2 vPos;
 
+#ifdef INPUT
+// Image of the previous effect of the chain
+uniform sampler2D Source;
+#endif
+
 layout(location = 0) out vec4 Color;
 
 // https://lospec.com/palette-list/fading-16
@@ -61,6 +72,7 @@ uniform vec3 ColorPalette[32] = vec3[](
 );
 
 uniform int NColors = 32;
+uniform float DitherSpread = 1.0 / 16.0;
 
 // https://en.wikipedia.org/wiki/Ordered_dithering
 const mat4 bayerMatrix = mat4(
@@ -74,7 +86,7 @@ float swirl(vec2 uv) {
     vec2 p = uv - 0.5;
     float angle = atan(p.y, p.x);
     float radius = length(p);
-    float t = Elapsed * 0.0001;
+    float t = Time * 0.1;
     return sin(6.0 * radius - t + angle);
 }
 
@@ -100,13 +112,20 @@ vec3 quantize(vec3 actualColor) {
 }
 
 void main() {
+#ifdef INPUT
+    vec3 c = texture(Source, vPos).rgb;
+#else
+    // Animated by the time of the frame uniform block
     vec3 c = uvToColor(vPos);
+#endif
 
+#ifdef DITHER
     ivec2 coords = ivec2(mod(gl_FragCoord.xy, 4.0));
 
     float threshold = bayerMatrix[coords.y][coords.x];
-    const float r = 1.0 / 16.0;
-    c = quantize(c + r * threshold);
+    c += DitherSpread * threshold;
+#endif
+    c = quantize(c);
 
     Color = vec4(c, 1.0);
 }
@@ -1,104 +1,170 @@
-use std::{sync::Arc, time::Instant};
+use std::rc::Rc;
 
 use anyhow::Result;
-use glow::{TEXTURE0, TRIANGLE_FAN, UniformLocation};
+use glow::{RGBA8, TEXTURE0, UniformLocation};
 use log::info;
+use serde::{Deserialize, Serialize};
 use winit::dpi::PhysicalSize;
 
 use seagull_lib::{
-    frame_buffer::FrameBuffer, shader::Shader, vertex_array::VertexArray,
-    vertex_buffer::VertexBuffer,
+    effect_params::EffectParams,
+    frame_buffer::{AttachmentDesc, FrameBufferDesc},
+    fullscreen_pass::FullscreenPass,
+    render_graph::{RenderGraph, ResourceId},
+    shader::Shader,
+    shader_variants::ShaderVariants,
 };
 
+use palette::{MAX_COLORS, Palette};
+
+pub mod palette;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, EffectParams)]
+#[serde(default)]
+pub struct PixelateParams {
+    /// Size of a pixelated pixel in screen pixels
+    #[param(default = 4.0, min = 1.0, max = 32.0)]
+    pub scale: f32,
+    /// Number of palette colors used, starting from the first one
+    #[param(default = 32, min = 2, max = MAX_COLORS)]
+    pub colors: i32,
+    /// Applies ordered dithering before quantizing to the palette
+    #[param(default = true)]
+    pub dithering: bool,
+    /// Strength of the dithering, in color units
+    #[param(default = 0.0625, min = 0.0, max = 0.5)]
+    pub dither_spread: f32,
+}
+
+///
+/// Quantizes an image to a palette at a lower resolution
+///
+/// Draws its own animated swirl when it has no input.
+///
 pub struct Pixelate {
-    scale: f32,
-    fbo: FrameBuffer,
-    fragment_shader: Shader,
+    params: PixelateParams,
+    palette: Palette,
+    size: PhysicalSize<u32>,
+    target_desc: FrameBufferDesc,
+    fragment_shader: ShaderVariants,
     display_shader: Shader,
     texture_loc: UniformLocation,
-    elapsed_loc: UniformLocation,
-    vertex_array: VertexArray,
-    start: Instant,
+    fullscreen_pass: Rc<FullscreenPass>,
 }
 
 impl Pixelate {
-    pub fn new(gl: Arc<glow::Context>, size: &PhysicalSize<u32>, scale: f32) -> Result<Self> {
-        let fbo = FrameBuffer::new(
-            gl.clone(),
-            (size.width as f32 / scale) as i32,
-            (size.height as f32 / scale) as i32,
-        )?;
-        let fragment_shader =
-            Shader::new(gl.clone(), include_str!("../VS.glsl"), include_str!("FS.glsl"))?;
-        let elapsed_loc = fragment_shader.get_loc("Elapsed")?;
-
-        let display_shader = Shader::new(
-            gl.clone(),
-            include_str!("../VS.glsl"),
-            include_str!("../Display-FS.glsl"),
-        )?;
-        let texture_loc = display_shader.get_loc("FBO")?;
+    pub fn new(
+        fullscreen_pass: Rc<FullscreenPass>,
+        size: &PhysicalSize<u32>,
+        params: PixelateParams,
+    ) -> Result<Self> {
+        let mut fragment_shader = ShaderVariants::new(
+            "pixelate",
+            fullscreen_pass.shader_builder(include_str!("FS.glsl")),
+        )
+        .toggle("DITHER")
+        .toggle("INPUT")
+        .uniforms(&["Source", "ColorPalette", "NColors", "DitherSpread"]);
+        fragment_shader.set_toggle("DITHER", params.dithering)?;
 
-        let vertices = [
-            -1.0, -1.0, 0.0, // bottom left
-            -1.0, 1.0, 0.0, // top left
-            1.0, 1.0, 0.0, // top right
-            1.0, -1.0, 0.0, // bottom right
-        ];
-        let vertex_buffer = VertexBuffer::new(gl.clone(), &vertices)?;
-        let vertex_array = VertexArray::new(gl.clone(), vertex_buffer)?;
+        let display_shader = fullscreen_pass.shader(include_str!("../Display-FS.glsl"))?;
+        display_shader.set_label("pixelate display");
+        let texture_loc = display_shader.get_loc("FBO")?;
 
         info!("Initialized pixelate effect");
 
         Ok(Self {
-            scale,
-            fbo,
+            target_desc: Self::target_desc(size, params.scale),
+            params,
+            palette: Palette::default(),
+            size: *size,
             fragment_shader,
             display_shader,
-            elapsed_loc,
             texture_loc,
-            vertex_array,
-            start: Instant::now(),
+            fullscreen_pass,
         })
     }
 
-    pub fn apply(
-        &self,
-        x: i32,
-        y: i32,
-        width: i32,
-        height: i32,
-        _source: Option<&FrameBuffer>,
-        target: Option<&FrameBuffer>,
-    ) -> Result<()> {
-        let time_since_start = self.start.elapsed();
-        self.fbo.bind();
-        self.fragment_shader.use_program();
-        self.fragment_shader
-            .uniform_1f(&self.elapsed_loc, time_since_start.as_millis() as f32);
-        self.vertex_array.draw(TRIANGLE_FAN);
-        self.fbo.unbind(x, y, width, height);
+    ///
+    /// Adds the pixelate passes to `graph`, drawing the pixelated `input`, or the swirl
+    /// when there is none, to `target`
+    ///
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        input: Option<ResourceId>,
+        target: ResourceId,
+    ) {
+        let pixels = graph.create("pixelate", self.target_desc.clone());
+        let reads: Vec<ResourceId> = input.into_iter().collect();
 
-        if let Some(target) = target {
-            target.bind();
-        }
+        graph.add_pass("pixelate", &reads, &[pixels], move |pass| {
+            let key = self.fragment_shader.with_toggle("INPUT", input.is_some())?;
+            let shader = self.fragment_shader.variant(&key)?;
+            let location = |name| self.fragment_shader.location(&key, name);
+            if let Some(input) = input {
+                pass.texture(input, 0)?.activate_texture(TEXTURE0);
+                shader.uniform_1i(&location("Source")?, 0);
+            }
+            shader.uniform_3f_slice(&location("ColorPalette")?, self.palette.colors());
+            let colors = (self.params.colors as usize).min(self.palette.len());
+            shader.uniform_1i(&location("NColors")?, colors as i32);
+            // Optimized out of the variant without dithering
+            if self.params.dithering {
+                shader.uniform_1f(&location("DitherSpread")?, self.params.dither_spread);
+            }
+            self.fullscreen_pass.draw(
+                &shader,
+                pass.frame_buffer(pixels),
+                pass.viewport(pixels),
+            );
+            Ok(())
+        });
 
-        self.display_shader.use_program();
-        self.fbo.texture.activate_texture(TEXTURE0);
-        self.display_shader.uniform_1i(&self.texture_loc, 0);
-        self.vertex_array.draw(TRIANGLE_FAN);
+        graph.add_pass("pixelate display", &[pixels], &[target], move |pass| {
+            self.display_shader.use_program();
+            pass.texture(pixels, 0)?.activate_texture(TEXTURE0);
+            self.display_shader.uniform_1i(&self.texture_loc, 0);
+            self.fullscreen_pass.draw(
+                &self.display_shader,
+                pass.frame_buffer(target),
+                pass.viewport(target),
+            );
+            Ok(())
+        });
+    }
 
-        if let Some(target) = target {
-            target.unbind(x, y, width, height);
-        }
+    pub fn params(&self) -> &PixelateParams {
+        &self.params
+    }
 
+    pub fn set_params(&mut self, params: PixelateParams) -> Result<()> {
+        self.fragment_shader
+            .set_toggle("DITHER", params.dithering)?;
+        self.target_desc = Self::target_desc(&self.size, params.scale);
+        self.params = params;
         Ok(())
     }
 
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
     pub fn resize(&mut self, size: &PhysicalSize<u32>) -> Result<()> {
-        self.fbo.resize(
-            (size.width as f32 / self.scale) as i32,
-            (size.height as f32 / self.scale) as i32,
+        self.size = *size;
+        self.target_desc = Self::target_desc(size, self.params.scale);
+        Ok(())
+    }
+
+    fn target_desc(size: &PhysicalSize<u32>, scale: f32) -> FrameBufferDesc {
+        FrameBufferDesc::new(
+            ((size.width as f32 / scale) as i32).max(1),
+            ((size.height as f32 / scale) as i32).max(1),
         )
+        .color(AttachmentDesc::texture(RGBA8))
     }
 }
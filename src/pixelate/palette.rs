@@ -0,0 +1,121 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+/// Size of the `ColorPalette` array of the fragment shader
+pub const MAX_COLORS: usize = 32;
+
+///
+/// Colors the pixelate effect quantizes to, as 0 to 1 RGB
+///
+/// Stored as hex strings like `"#5ba675"`, palette files have one such color per line
+/// like the `.hex` files of lospec.
+///
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct Palette {
+    colors: Vec<[f32; 3]>,
+}
+
+impl Palette {
+    ///
+    /// Keeps at most `MAX_COLORS` colors
+    ///
+    pub fn new(mut colors: Vec<[f32; 3]>) -> Result<Self> {
+        if colors.is_empty() {
+            return Err(anyhow::anyhow!("Palette has no color"));
+        }
+        colors.truncate(MAX_COLORS);
+        Ok(Self { colors })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read palette {}", path.display()))?;
+        let palette =
+            Self::parse(&source).with_context(|| format!("Invalid palette {}", path.display()))?;
+
+        info!(
+            "Loaded palette {} of {} colors",
+            path.display(),
+            palette.len()
+        );
+
+        Ok(palette)
+    }
+
+    ///
+    /// Parses one hex color per line, blank lines and lines starting with `;` are skipped
+    ///
+    pub fn parse(source: &str) -> Result<Self> {
+        let colors = source
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with(';'))
+            .map(parse_hex)
+            .collect::<Result<Vec<_>>>()?;
+        Self::new(colors)
+    }
+
+    pub fn colors(&self) -> &[[f32; 3]] {
+        &self.colors
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    ///
+    /// Colors as 8 bit RGB
+    ///
+    pub fn to_rgb8(&self) -> Vec<[u8; 3]> {
+        self.colors
+            .iter()
+            .map(|color| color.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8))
+            .collect()
+    }
+}
+
+impl Default for Palette {
+    ///
+    /// The 32 colors the fragment shader starts with
+    ///
+    fn default() -> Self {
+        Self::parse(include_str!("default.hex")).unwrap()
+    }
+}
+
+impl TryFrom<Vec<String>> for Palette {
+    type Error = anyhow::Error;
+
+    fn try_from(colors: Vec<String>) -> Result<Self> {
+        Self::new(
+            colors
+                .iter()
+                .map(|color| parse_hex(color))
+                .collect::<Result<_>>()?,
+        )
+    }
+}
+
+impl From<Palette> for Vec<String> {
+    fn from(palette: Palette) -> Self {
+        palette
+            .to_rgb8()
+            .iter()
+            .map(|[r, g, b]| format!("#{r:02x}{g:02x}{b:02x}"))
+            .collect()
+    }
+}
+
+fn parse_hex(color: &str) -> Result<[f32; 3]> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    let value = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6)
+        .with_context(|| format!("Invalid color '{color}', expected RRGGBB"))?;
+    Ok([16, 8, 0].map(|shift| ((value >> shift) & 0xff) as f32 / 255.0))
+}
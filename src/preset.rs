@@ -0,0 +1,242 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    effect::{self, EffectKind},
+    pixelate::{PixelateParams, palette::Palette},
+    voronoi::VoronoiParams,
+};
+
+/// Version written to new presets, older ones are migrated when loaded
+pub const PRESET_VERSION: u32 = 1;
+
+///
+/// Upgrades of a preset table, the one at `index` going from version `index + 1` to the next
+///
+/// A change of the format bumps `PRESET_VERSION` and appends its migration here.
+///
+const MIGRATIONS: &[fn(&mut toml::Table) -> Result<()>] = &[];
+
+const _: () = assert!(MIGRATIONS.len() as u32 == PRESET_VERSION - 1);
+
+/// File remembering the last presets loaded or saved
+pub const RECENT_PRESETS_PATH: &str = "recent_presets.toml";
+const MAX_RECENT_PRESETS: usize = 8;
+
+///
+/// Everything needed to get the same image back: the effect chain, their parameters,
+/// the sites, the palette and the seed
+///
+/// Saved as TOML, for example:
+///
+/// ```toml
+/// version = 1
+/// seed = 42
+/// chain = ["voronoi", "pixelate"]
+///
+/// [voronoi]
+/// sites = [[0.25, 0.5], [0.75, 0.5]]
+///
+/// [voronoi.params]
+/// sites = 2
+/// metric = "manhattan"
+/// ```
+///
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Preset {
+    pub version: u32,
+    pub seed: u64,
+    pub chain: Vec<EffectKind>,
+    pub voronoi: VoronoiPreset,
+    #[serde(default)]
+    pub pixelate: PixelatePreset,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VoronoiPreset {
+    /// Positions in `vPos` space
+    pub sites: Vec<[f32; 2]>,
+    #[serde(default)]
+    pub params: VoronoiParams,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PixelatePreset {
+    #[serde(default)]
+    pub palette: Palette,
+    #[serde(default)]
+    pub params: PixelateParams,
+}
+
+impl Preset {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read preset {}", path.display()))?;
+        let preset =
+            Self::parse(&source).with_context(|| format!("Invalid preset {}", path.display()))?;
+
+        info!("Loaded preset {}", path.display());
+
+        Ok(preset)
+    }
+
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut table: toml::Table = toml::from_str(source)?;
+        migrate(&mut table)?;
+        let preset: Self = table.try_into()?;
+        effect::validate_chain(&preset.chain)?;
+        Ok(preset)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        fs::write(path, toml::to_string_pretty(self)?)
+            .with_context(|| format!("Unable to write preset {}", path.display()))?;
+
+        info!("Saved preset {}", path.display());
+
+        Ok(())
+    }
+}
+
+///
+/// Brings a preset of any older version to `PRESET_VERSION`
+///
+fn migrate(table: &mut toml::Table) -> Result<()> {
+    let version = table
+        .get("version")
+        .and_then(toml::Value::as_integer)
+        .context("Preset has no version")?;
+    let version = u32::try_from(version)
+        .ok()
+        .filter(|version| *version >= 1)
+        .with_context(|| format!("Invalid preset version {version}"))?;
+    if version > PRESET_VERSION {
+        return Err(anyhow::anyhow!(
+            "Preset version {version} is newer than the supported version {PRESET_VERSION}"
+        ));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        migration(table)
+            .with_context(|| format!("Unable to migrate preset from version {}", index + 1))?;
+        table.insert("version".to_owned(), toml::Value::Integer(index as i64 + 2));
+        info!("Migrated preset to version {}", index + 2);
+    }
+
+    Ok(())
+}
+
+///
+/// Presets last loaded or saved, most recent first
+///
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RecentPresets {
+    paths: Vec<PathBuf>,
+}
+
+impl RecentPresets {
+    ///
+    /// Reads the list, starting empty when the file is missing or invalid
+    ///
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let Ok(source) = fs::read_to_string(path) else {
+            return Self::default();
+        };
+        toml::from_str(&source).unwrap_or_else(|error| {
+            warn!("Invalid recent presets {}: {error}", path.display());
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    pub fn push(&mut self, path: &Path) {
+        self.paths.retain(|recent| recent != path);
+        self.paths.insert(0, path.to_owned());
+        self.paths.truncate(MAX_RECENT_PRESETS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preset() -> Preset {
+        Preset {
+            version: PRESET_VERSION,
+            seed: 42,
+            chain: vec![EffectKind::Voronoi, EffectKind::Pixelate],
+            voronoi: VoronoiPreset {
+                sites: vec![[0.25, 0.5], [0.75, 0.5]],
+                params: VoronoiParams {
+                    sites: 2,
+                    ..VoronoiParams::default()
+                },
+            },
+            pixelate: PixelatePreset::default(),
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = std::env::temp_dir().join(format!("seagull-preset-{}.toml", std::process::id()));
+        let saved = preset();
+        saved.save(&path).unwrap();
+        let loaded = Preset::load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.version, saved.version);
+        assert_eq!(loaded.seed, saved.seed);
+        assert_eq!(loaded.chain, saved.chain);
+        assert_eq!(loaded.voronoi.sites, saved.voronoi.sites);
+        assert_eq!(loaded.voronoi.params, saved.voronoi.params);
+        assert_eq!(loaded.pixelate.palette, saved.pixelate.palette);
+        assert_eq!(loaded.pixelate.params, saved.pixelate.params);
+    }
+
+    #[test]
+    fn parse_rejects_newer_version() {
+        let source = toml::to_string(&Preset {
+            version: PRESET_VERSION + 1,
+            ..preset()
+        })
+        .unwrap();
+        let error = Preset::parse(&source).unwrap_err();
+        assert!(error.to_string().contains("newer"), "{error}");
+    }
+
+    #[test]
+    fn parse_rejects_missing_version() {
+        let mut table = toml::Table::try_from(preset()).unwrap();
+        table.remove("version");
+        let error = Preset::parse(&table.to_string()).unwrap_err();
+        assert!(error.to_string().contains("no version"), "{error}");
+    }
+
+    #[test]
+    fn parse_rejects_chain_dropping_an_input() {
+        let source = toml::to_string(&Preset {
+            chain: vec![EffectKind::Pixelate, EffectKind::Voronoi],
+            ..preset()
+        })
+        .unwrap();
+        assert!(Preset::parse(&source).is_err());
+    }
+}
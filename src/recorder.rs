@@ -0,0 +1,437 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use egui::DragValue;
+use glow::RGBA8;
+use log::info;
+use seagull_lib::{
+    effect_params::{Param, ParamChoice, ParamInfo},
+    frame_buffer::{AttachmentDesc, FrameBuffer, FrameBufferDesc},
+    gl_state::GlState,
+};
+use winit::dpi::PhysicalSize;
+
+use crate::pixelate::palette::Palette;
+
+/// Colors whose palette index a GIF recording remembers, the cache being emptied when full
+const MAX_CACHED_COLORS: usize = 1 << 16;
+
+///
+/// File format of a recording
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RecordFormat {
+    /// Numbered PNG files in a directory
+    Png,
+    /// Uncompressed 4:4:4 YUV4MPEG2 video, readable by ffmpeg and most players
+    Y4m,
+    /// Looping animated GIF using the colors of the pixelate palette
+    Gif,
+}
+
+impl RecordFormat {
+    pub const ALL: [RecordFormat; 3] = [RecordFormat::Png, RecordFormat::Y4m, RecordFormat::Gif];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RecordFormat::Png => "png",
+            RecordFormat::Y4m => "y4m",
+            RecordFormat::Gif => "gif",
+        }
+    }
+
+    ///
+    /// Directory for PNG sequences, file for the video formats
+    ///
+    pub fn default_output(self) -> PathBuf {
+        match self {
+            RecordFormat::Png => PathBuf::from("frames"),
+            RecordFormat::Y4m => PathBuf::from("recording.y4m"),
+            RecordFormat::Gif => PathBuf::from("recording.gif"),
+        }
+    }
+}
+
+impl ParamChoice for RecordFormat {
+    const ALL: &'static [Self] = &RecordFormat::ALL;
+
+    fn name(self) -> &'static str {
+        RecordFormat::name(self)
+    }
+}
+
+///
+/// What to record and where
+///
+#[derive(Clone, Debug)]
+pub struct RecorderConfig {
+    pub format: RecordFormat,
+    pub resolution: PhysicalSize<u32>,
+    /// Frames to record, `None` to record until stopped
+    pub frames: Option<u32>,
+    /// Simulated seconds between two frames, whatever the time they take to render
+    pub time_step: f32,
+    pub output: PathBuf,
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        Self {
+            format: RecordFormat::Png,
+            resolution: PhysicalSize::new(1920, 1080),
+            frames: None,
+            time_step: 1.0 / 60.0,
+            output: RecordFormat::Png.default_output(),
+        }
+    }
+}
+
+impl RecorderConfig {
+    ///
+    /// Frames per second as a fraction, `n:1` when the step is a whole fraction of a second
+    /// and otherwise the step rounded to the microsecond
+    ///
+    fn frame_rate(&self) -> (u32, u32) {
+        let rate = 1.0 / self.time_step as f64;
+        if (rate - rate.round()).abs() < 1e-3 {
+            return ((rate.round() as u32).max(1), 1);
+        }
+        (1_000_000, ((self.time_step * 1e6).round() as u32).max(1))
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("RecorderConfig")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Format");
+                let format = self.format;
+                let info = ParamInfo {
+                    name: "format",
+                    ..ParamInfo::DEFAULT
+                };
+                if self.format.ui(ui, &info) && self.output == format.default_output() {
+                    self.output = self.format.default_output();
+                }
+                ui.end_row();
+
+                ui.label("Resolution");
+                ui.horizontal(|ui| {
+                    ui.add(DragValue::new(&mut self.resolution.width).range(1..=16384));
+                    ui.label("x");
+                    ui.add(DragValue::new(&mut self.resolution.height).range(1..=16384));
+                });
+                ui.end_row();
+
+                ui.label("Frames");
+                let mut frames = self.frames.unwrap_or(0);
+                ui.add(DragValue::new(&mut frames))
+                    .on_hover_text("0 records until stopped");
+                self.frames = (frames > 0).then_some(frames);
+                ui.end_row();
+
+                ui.label("Time step");
+                ui.add(
+                    DragValue::new(&mut self.time_step)
+                        .speed(0.001)
+                        .range(0.001..=1.0),
+                );
+                ui.end_row();
+
+                ui.label("Output");
+                let mut output = self.output.display().to_string();
+                if ui.text_edit_singleline(&mut output).changed() {
+                    self.output = output.into();
+                }
+                ui.end_row();
+            });
+    }
+}
+
+enum FrameWriter {
+    Png,
+    Y4m(BufWriter<File>),
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        palette: Vec<[u8; 3]>,
+        /// Palette index of the colors met so far, effects usually drawing only a few colors
+        indices: HashMap<[u8; 3], u8>,
+    },
+}
+
+///
+/// Offline capture of frames rendered at a fixed resolution into its own frame buffer
+///
+/// Whoever drives it renders a frame to `frame_buffer` and calls `capture`, the clock being
+/// set to `ClockMode::FixedStep` with `time_step` so that the frames do not depend on how long
+/// they take to render.
+///
+pub struct Recorder {
+    config: RecorderConfig,
+    frame_buffer: FrameBuffer,
+    writer: FrameWriter,
+    frame: u32,
+}
+
+impl Recorder {
+    ///
+    /// Creates the output, `palette` giving the colors of GIF recordings
+    ///
+    pub fn new(gl: Rc<GlState>, config: RecorderConfig, palette: &Palette) -> Result<Self> {
+        let PhysicalSize { width, height } = config.resolution;
+        let writer = match config.format {
+            RecordFormat::Png => {
+                fs::create_dir_all(&config.output)
+                    .with_context(|| format!("Unable to create {}", config.output.display()))?;
+                FrameWriter::Png
+            }
+            RecordFormat::Y4m => {
+                let mut file = create_file(&config.output)?;
+                let (numerator, denominator) = config.frame_rate();
+                writeln!(
+                    file,
+                    "YUV4MPEG2 W{width} H{height} F{numerator}:{denominator} Ip A1:1 C444"
+                )?;
+                FrameWriter::Y4m(file)
+            }
+            RecordFormat::Gif => {
+                let (Ok(gif_width), Ok(gif_height)) = (u16::try_from(width), u16::try_from(height))
+                else {
+                    return Err(anyhow::anyhow!(
+                        "GIF recordings are at most {}x{}",
+                        u16::MAX,
+                        u16::MAX
+                    ));
+                };
+                let palette = palette.to_rgb8();
+                let mut encoder = gif::Encoder::new(
+                    create_file(&config.output)?,
+                    gif_width,
+                    gif_height,
+                    palette.as_flattened(),
+                )?;
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+                FrameWriter::Gif {
+                    encoder,
+                    palette,
+                    indices: HashMap::new(),
+                }
+            }
+        };
+
+        let desc =
+            FrameBufferDesc::new(width as i32, height as i32).color(AttachmentDesc::texture(RGBA8));
+        let mut frame_buffer = FrameBuffer::from_desc(gl, desc)?;
+        frame_buffer.set_label("recorder");
+
+        info!(
+            "Recording {width}x{height} {} to {}",
+            config.format.name(),
+            config.output.display()
+        );
+
+        Ok(Self {
+            config,
+            frame_buffer,
+            writer,
+            frame: 0,
+        })
+    }
+
+    pub fn config(&self) -> &RecorderConfig {
+        &self.config
+    }
+
+    ///
+    /// Target the next frame is rendered to
+    ///
+    pub fn frame_buffer(&self) -> &FrameBuffer {
+        &self.frame_buffer
+    }
+
+    ///
+    /// Number of frames written so far
+    ///
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    ///
+    /// Writes the frame just rendered, returns whether every requested frame has been written
+    ///
+    pub fn capture(&mut self) -> Result<bool> {
+        let size = self.config.resolution;
+        let pixels = flip_rows(&self.frame_buffer.read_pixels()?, size.width);
+
+        match &mut self.writer {
+            FrameWriter::Png => {
+                let path = self
+                    .config
+                    .output
+                    .join(format!("frame_{:05}.png", self.frame));
+                write_png(&path, size, &pixels)?;
+            }
+            FrameWriter::Y4m(file) => {
+                let planes = rgba_to_yuv444(&pixels);
+                file.write_all(b"FRAME\n")?;
+                for plane in &planes {
+                    file.write_all(plane)?;
+                }
+            }
+            FrameWriter::Gif {
+                encoder,
+                palette,
+                indices,
+            } => {
+                let buffer: Vec<u8> = pixels
+                    .chunks_exact(4)
+                    .map(|pixel| {
+                        let color = [pixel[0], pixel[1], pixel[2]];
+                        if let Some(&index) = indices.get(&color) {
+                            return index;
+                        }
+                        if indices.len() >= MAX_CACHED_COLORS {
+                            indices.clear();
+                        }
+                        let index = nearest_color(palette, color);
+                        indices.insert(color, index);
+                        index
+                    })
+                    .collect();
+                encoder.write_frame(&gif::Frame {
+                    width: size.width as u16,
+                    height: size.height as u16,
+                    // GIF delays are in hundredths of a second
+                    delay: (self.config.time_step * 100.0).round().max(1.0) as u16,
+                    buffer: buffer.into(),
+                    ..gif::Frame::default()
+                })?;
+            }
+        }
+        self.frame += 1;
+
+        Ok(self
+            .config
+            .frames
+            .is_some_and(|frames| self.frame >= frames))
+    }
+
+    ///
+    /// Completes the output, video files are not readable until then
+    ///
+    pub fn finish(self) -> Result<()> {
+        match self.writer {
+            FrameWriter::Png => {}
+            FrameWriter::Y4m(mut file) => file.flush()?,
+            FrameWriter::Gif { encoder, .. } => encoder.into_inner()?.flush()?,
+        }
+
+        info!(
+            "Recorded {} frames to {}",
+            self.frame,
+            self.config.output.display()
+        );
+
+        Ok(())
+    }
+}
+
+fn create_file(path: &Path) -> Result<BufWriter<File>> {
+    let file =
+        File::create(path).with_context(|| format!("Unable to create {}", path.display()))?;
+    Ok(BufWriter::new(file))
+}
+
+///
+/// Turns the bottom to top rows read from GL into top to bottom rows
+///
+fn flip_rows(pixels: &[u8], width: u32) -> Vec<u8> {
+    pixels
+        .chunks_exact(width as usize * 4)
+        .rev()
+        .flatten()
+        .copied()
+        .collect()
+}
+
+///
+/// Writes `RGBA8` pixels, top row first, as a PNG file
+///
+pub fn write_png(path: &Path, size: PhysicalSize<u32>, pixels: &[u8]) -> Result<()> {
+    let mut encoder = png::Encoder::new(create_file(path)?, size.width, size.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer
+        .write_image_data(pixels)
+        .with_context(|| format!("Unable to write {}", path.display()))?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+///
+/// Y, Cb and Cr planes of `RGBA8` pixels, with the BT.601 limited range of YUV4MPEG2
+///
+fn rgba_to_yuv444(pixels: &[u8]) -> [Vec<u8>; 3] {
+    let count = pixels.len() / 4;
+    let mut planes = [
+        Vec::with_capacity(count),
+        Vec::with_capacity(count),
+        Vec::with_capacity(count),
+    ];
+    for pixel in pixels.chunks_exact(4) {
+        let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|channel| channel as f32 / 255.0);
+        planes[0].push((16.0 + 65.481 * r + 128.553 * g + 24.966 * b).round() as u8);
+        planes[1].push((128.0 - 37.797 * r - 74.203 * g + 112.0 * b).round() as u8);
+        planes[2].push((128.0 + 112.0 * r - 93.786 * g - 18.214 * b).round() as u8);
+    }
+    planes
+}
+
+fn nearest_color(palette: &[[u8; 3]], color: [u8; 3]) -> u8 {
+    let distance = |candidate: &[u8; 3]| -> i32 {
+        candidate
+            .iter()
+            .zip(color)
+            .map(|(&a, b)| (a as i32 - b as i32).pow(2))
+            .sum()
+    };
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, candidate)| distance(candidate))
+        .map_or(0, |(index, _)| index as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_rate(time_step: f32) -> (u32, u32) {
+        RecorderConfig {
+            time_step,
+            ..RecorderConfig::default()
+        }
+        .frame_rate()
+    }
+
+    #[test]
+    fn whole_frame_rates_are_exact() {
+        assert_eq!(frame_rate(1.0 / 60.0), (60, 1));
+        assert_eq!(frame_rate(1.0 / 24.0), (24, 1));
+        assert_eq!(frame_rate(0.5), (2, 1));
+    }
+
+    #[test]
+    fn other_frame_rates_use_microseconds() {
+        assert_eq!(frame_rate(0.0123), (1_000_000, 12_300));
+    }
+}
@@ -1,23 +1,48 @@
 #version 460
 
-in vec2 vPos;
+// Evaluated per sample so the cell borders are anti-aliased on multisampled targets
+sample in vec2 vPos;
 
 layout(location = 0) out vec4 Color;
 
-uniform vec2 Points[16];
+const float SITE_RADIUS = 0.004;
 
-vec3 getColorFromPos(vec2 pos) {
-    vec2 nearestPoint = Points[0];
+layout(std430, binding = 0) readonly buffer Sites {
+    vec2 Points[];
+};
+uniform int NPoints = 1;
+uniform int Hovered = -1;
 
-    for (int i = 1; i < 16; i++) {
-        vec2 point = Points[i];
-        bool isNearer = distance(pos, point) < distance(nearestPoint, point);
-        nearestPoint = float(isNearer) * point + float(!isNearer) * nearestPoint;
+// METRIC is one of METRIC_EUCLIDEAN, METRIC_MANHATTAN and METRIC_CHEBYSHEV, set by the variant
+float metricDistance(vec2 a, vec2 b) {
+    vec2 d = abs(a - b);
+#if METRIC == METRIC_MANHATTAN
+    return d.x + d.y;
+#elif METRIC == METRIC_CHEBYSHEV
+    return max(d.x, d.y);
+#else
+    return length(d);
+#endif
+}
+
+int getNearestPoint(vec2 pos) {
+    int nearest = 0;
+
+    for (int i = 1; i < NPoints; i++) {
+        bool isNearer = metricDistance(pos, Points[i]) < metricDistance(pos, Points[nearest]);
+        nearest = isNearer ? i : nearest;
     }
 
-    return vec3(nearestPoint, 1.0);
+    return nearest;
 }
 
 void main(){
-    Color = vec4(getColorFromPos(vPos), 1.0);
+    int nearest = getNearestPoint(vPos);
+    vec2 nearestPoint = Points[nearest];
+
+    vec3 color = vec3(nearestPoint, 1.0);
+    color = mix(color, vec3(1.0), float(nearest == Hovered) * 0.35);
+    color *= smoothstep(SITE_RADIUS, SITE_RADIUS * 1.5, distance(vPos, nearestPoint));
+
+    Color = vec4(color, 1.0);
 }
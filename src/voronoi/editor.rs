@@ -0,0 +1,193 @@
+use glm::Vec2;
+use log::info;
+use seagull_lib::input::Input;
+use winit::event::MouseButton;
+
+use super::{MAX_POINTS, Voronoi, distance};
+
+/// Distance in `vPos` space under which the cursor grabs a site
+const PICK_RADIUS: f32 = 0.025;
+
+#[derive(Clone, Debug)]
+enum Edit {
+    Move { index: usize, from: Vec2, to: Vec2 },
+    Add { index: usize, point: Vec2 },
+    Remove { index: usize, point: Vec2 },
+    Replace { from: Vec<Vec2>, to: Vec<Vec2> },
+}
+
+impl Edit {
+    fn apply(&self, voronoi: &mut Voronoi) {
+        match self {
+            Edit::Move { index, to, .. } => voronoi.set_point(*index, *to),
+            Edit::Add { index, point } => voronoi.insert_point(*index, *point),
+            Edit::Remove { index, .. } => {
+                voronoi.remove_point(*index);
+            }
+            Edit::Replace { to, .. } => voronoi.set_points(to.clone()),
+        }
+    }
+
+    fn revert(&self, voronoi: &mut Voronoi) {
+        match self {
+            Edit::Move { index, from, .. } => voronoi.set_point(*index, *from),
+            Edit::Add { index, .. } => {
+                voronoi.remove_point(*index);
+            }
+            Edit::Remove { index, point } => voronoi.insert_point(*index, *point),
+            Edit::Replace { from, .. } => voronoi.set_points(from.clone()),
+        }
+    }
+}
+
+struct Drag {
+    index: usize,
+    from: Vec2,
+}
+
+///
+/// Edits the sites of a `Voronoi` with the mouse
+///
+/// Left click drags the site under the cursor or adds a new one on empty space,
+/// right click deletes the site under the cursor. Every edit can be undone.
+///
+#[derive(Default)]
+pub struct SiteEditor {
+    drag: Option<Drag>,
+    hovered: Option<usize>,
+    undo_stack: Vec<Edit>,
+    redo_stack: Vec<Edit>,
+}
+
+impl SiteEditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// Site within picking distance of a position in `vPos` space
+    ///
+    pub fn pick(voronoi: &Voronoi, pos: Vec2) -> Option<usize> {
+        voronoi
+            .nearest(pos)
+            .filter(|index| distance(voronoi.points()[*index], pos) <= PICK_RADIUS)
+    }
+
+    pub fn update(&mut self, voronoi: &mut Voronoi, input: &Input) {
+        if input.is_action_pressed("undo") {
+            self.undo(voronoi);
+        }
+        if input.is_action_pressed("redo") {
+            self.redo(voronoi);
+        }
+
+        let Some(cursor) = input.cursor_vpos() else {
+            self.end_drag(voronoi);
+            self.set_hovered(voronoi, None);
+            return;
+        };
+
+        if let Some(drag) = &self.drag {
+            voronoi.set_point(drag.index, cursor);
+            if !input.is_button_held(MouseButton::Left) {
+                self.end_drag(voronoi);
+            }
+        } else if input.is_button_pressed(MouseButton::Left) {
+            match Self::pick(voronoi, cursor) {
+                Some(index) => {
+                    self.drag = Some(Drag {
+                        index,
+                        from: voronoi.points()[index],
+                    });
+                }
+                None if voronoi.points().len() < MAX_POINTS => {
+                    let index = voronoi.points().len();
+                    self.push(
+                        voronoi,
+                        Edit::Add {
+                            index,
+                            point: cursor,
+                        },
+                    );
+                }
+                None => info!("Voronoi already has {MAX_POINTS} sites"),
+            }
+        } else if input.is_button_pressed(MouseButton::Right)
+            && let Some(index) = Self::pick(voronoi, cursor).filter(|_| voronoi.points().len() > 1)
+        {
+            let point = voronoi.points()[index];
+            self.push(voronoi, Edit::Remove { index, point });
+        }
+
+        let hovered = match &self.drag {
+            Some(drag) => Some(drag.index),
+            None => Self::pick(voronoi, cursor),
+        };
+        self.set_hovered(voronoi, hovered);
+    }
+
+    ///
+    /// Replaces every site as a single undoable edit
+    ///
+    pub fn replace(&mut self, voronoi: &mut Voronoi, points: Vec<Vec2>) {
+        self.end_drag(voronoi);
+        let from = voronoi.points().to_vec();
+        self.push(voronoi, Edit::Replace { from, to: points });
+    }
+
+    pub fn undo(&mut self, voronoi: &mut Voronoi) {
+        self.end_drag(voronoi);
+        if let Some(edit) = self.undo_stack.pop() {
+            edit.revert(voronoi);
+            self.redo_stack.push(edit);
+        }
+    }
+
+    pub fn redo(&mut self, voronoi: &mut Voronoi) {
+        self.end_drag(voronoi);
+        if let Some(edit) = self.redo_stack.pop() {
+            edit.apply(voronoi);
+            self.undo_stack.push(edit);
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    fn push(&mut self, voronoi: &mut Voronoi, edit: Edit) {
+        edit.apply(voronoi);
+        self.undo_stack.push(edit);
+        self.redo_stack.clear();
+    }
+
+    ///
+    /// Records the drag in progress, the site is already at its final position
+    ///
+    fn end_drag(&mut self, voronoi: &Voronoi) {
+        let Some(drag) = self.drag.take() else {
+            return;
+        };
+
+        let to = voronoi.points()[drag.index];
+        if to != drag.from {
+            self.undo_stack.push(Edit::Move {
+                index: drag.index,
+                from: drag.from,
+                to,
+            });
+            self.redo_stack.clear();
+        }
+    }
+
+    fn set_hovered(&mut self, voronoi: &Voronoi, hovered: Option<usize>) {
+        if self.hovered != hovered {
+            self.hovered = hovered;
+            voronoi.set_hovered(hovered);
+        }
+    }
+}
@@ -1,56 +1,225 @@
-use std::sync::Arc;
+use std::{cell::Cell, rc::Rc};
 
 use anyhow::Result;
 use glm::Vec2;
-use glow::{HasContext, UniformLocation, FRAMEBUFFER, TRIANGLE_FAN};
-use seagull_lib::{shader::Shader, vertex_array::VertexArray, vertex_buffer::VertexBuffer};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use seagull_lib::{
+    effect_params::{EffectParams, ParamChoice},
+    frame_buffer::FrameBuffer,
+    fullscreen_pass::{FullscreenPass, Viewport},
+    gl_state::GlState,
+    shader_buffer::StorageBuffer,
+    shader_variants::ShaderVariants,
+};
 
+pub mod editor;
+
+/// Capacity of the sites storage buffer
+pub const MAX_POINTS: usize = 64;
+/// Must match the binding of the `Sites` block in the fragment shader
+const SITES_BINDING: u32 = 0;
+
+///
+/// Distance between two positions in `vPos` space
+///
+pub fn distance(a: Vec2, b: Vec2) -> f32 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
+
+///
+/// Distance deciding which site a position belongs to
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    #[default]
+    Euclidean,
+    Manhattan,
+    Chebyshev,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 3] = [Metric::Euclidean, Metric::Manhattan, Metric::Chebyshev];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::Euclidean => "Euclidean",
+            Metric::Manhattan => "Manhattan",
+            Metric::Chebyshev => "Chebyshev",
+        }
+    }
+
+    ///
+    /// Value of the `METRIC` keyword of the fragment shader
+    ///
+    fn keyword(self) -> &'static str {
+        match self {
+            Metric::Euclidean => "METRIC_EUCLIDEAN",
+            Metric::Manhattan => "METRIC_MANHATTAN",
+            Metric::Chebyshev => "METRIC_CHEBYSHEV",
+        }
+    }
+
+    pub fn distance(self, a: Vec2, b: Vec2) -> f32 {
+        let (dx, dy) = ((a.x - b.x).abs(), (a.y - b.y).abs());
+        match self {
+            Metric::Euclidean => (dx * dx + dy * dy).sqrt(),
+            Metric::Manhattan => dx + dy,
+            Metric::Chebyshev => dx.max(dy),
+        }
+    }
+}
+
+impl ParamChoice for Metric {
+    const ALL: &'static [Self] = &Metric::ALL;
+
+    fn name(self) -> &'static str {
+        Metric::name(self)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, EffectParams)]
+#[serde(default)]
+pub struct VoronoiParams {
+    /// Number of sites, changing it scatters new random sites
+    #[param(default = 16, min = 1, max = MAX_POINTS)]
+    pub sites: usize,
+    /// Distance deciding which site a pixel belongs to
+    #[param(default = Metric::Euclidean)]
+    pub metric: Metric,
+}
+
+///
+/// Voronoi diagram of a set of sites, each given in `vPos` space (0 to 1 on both axes)
+///
 pub struct Voronoi {
     points: Vec<Vec2>,
-    shader_program: Shader,
-    points_loc: UniformLocation,
-    vertex_array: VertexArray,
-    gl: Arc<glow::Context>,
+    params: VoronoiParams,
+    hovered: Cell<Option<usize>>,
+    shader: ShaderVariants,
+    points_buffer: StorageBuffer<[f32; 2]>,
+    fullscreen_pass: Rc<FullscreenPass>,
 }
 
 impl Voronoi {
-    pub fn new(gl: Arc<glow::Context>, points: Vec<Vec2>) -> Result<Self> {
-        let shader_program = Shader::new(
-            gl.clone(),
-            include_str!("../VS.glsl"),
-            include_str!("FS.glsl"),
-        )?;
-
-        let points_loc = shader_program.get_loc("Points")?;
-        shader_program.uniform_2f_slice(&points_loc, &points);
-
-        let vertices = [
-            -1.0, -1.0, 0.0, // bottom left
-            -1.0, 1.0, 0.0, // top left
-            1.0, 1.0, 0.0, // top right
-            1.0, -1.0, 0.0, // bottom right
-        ];
-
-        let vertex_buffer = VertexBuffer::new(gl.clone(), &vertices)?;
-        let vertex_array = VertexArray::new(gl.clone(), vertex_buffer)?;
-
-        Ok(Self {
-            points,
-            shader_program,
-            points_loc,
-            vertex_array,
-            gl,
-        })
-    }
-
-    pub fn apply(&self, x: i32, y: i32, width: i32, height: i32) -> Result<()> {
-        unsafe {
-            self.gl.viewport(x, y, width, height);
-            self.gl.bind_framebuffer(FRAMEBUFFER, None);
+    pub fn new(
+        gl: Rc<GlState>,
+        fullscreen_pass: Rc<FullscreenPass>,
+        points: Vec<Vec2>,
+    ) -> Result<Self> {
+        let keywords = Metric::ALL.map(Metric::keyword);
+        let shader = ShaderVariants::new(
+            "voronoi",
+            fullscreen_pass.shader_builder(include_str!("FS.glsl")),
+        )
+        .choice("METRIC", &keywords)
+        .uniforms(&["NPoints", "Hovered"]);
+        // Few enough to compile them all, so switching the metric never stalls a frame
+        shader.warm_up(&shader.keys())?;
+
+        let points_buffer = StorageBuffer::zeroed(gl, MAX_POINTS)?;
+        points_buffer.set_label("voronoi sites");
+
+        let mut voronoi = Self {
+            points: Vec::new(),
+            params: VoronoiParams::default(),
+            hovered: Cell::new(None),
+            shader,
+            points_buffer,
+            fullscreen_pass,
+        };
+        voronoi.set_points(points);
+
+        Ok(voronoi)
+    }
+
+    pub fn points(&self) -> &[Vec2] {
+        &self.points
+    }
+
+    pub fn params(&self) -> &VoronoiParams {
+        &self.params
+    }
+
+    ///
+    /// Applies new parameters, the sites must already have been changed to match `sites`
+    ///
+    pub fn set_params(&mut self, params: VoronoiParams) -> Result<()> {
+        self.shader.set_choice("METRIC", params.metric.keyword())?;
+        self.params = params;
+        Ok(())
+    }
+
+    ///
+    /// Replaces every site, keeping at most `MAX_POINTS` of them
+    ///
+    pub fn set_points(&mut self, mut points: Vec<Vec2>) {
+        points.truncate(MAX_POINTS);
+        self.points = points;
+        self.upload_points();
+    }
+
+    pub fn set_point(&mut self, index: usize, point: Vec2) {
+        self.points[index] = point;
+        self.upload_points();
+    }
+
+    pub fn insert_point(&mut self, index: usize, point: Vec2) {
+        self.points.insert(index, point);
+        self.upload_points();
+    }
+
+    pub fn remove_point(&mut self, index: usize) -> Vec2 {
+        let point = self.points.remove(index);
+        self.upload_points();
+        point
+    }
+
+    ///
+    /// Index of the site nearest to a position in `vPos` space
+    ///
+    pub fn nearest(&self, pos: Vec2) -> Option<usize> {
+        self.points
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                let metric = self.params.metric;
+                metric.distance(pos, **a).total_cmp(&metric.distance(pos, **b))
+            })
+            .map(|(index, _)| index)
+    }
+
+    ///
+    /// Highlights the cell of a site
+    ///
+    pub fn set_hovered(&self, index: Option<usize>) {
+        self.hovered.set(index);
+    }
+
+    fn upload_points(&mut self) {
+        self.params.sites = self.points.len();
+        let points: Vec<[f32; 2]> = self.points.iter().map(|point| [point.x, point.y]).collect();
+        if let Err(error) = self.points_buffer.update(0, &points) {
+            warn!("{error}");
         }
+    }
+
+    pub fn apply(&self, target: Option<&FrameBuffer>, viewport: Viewport) -> Result<()> {
+        // Every variant has its own uniforms, they are set on the one drawing
+        let key = self.shader.key();
+        let shader = self.shader.shader()?;
+        shader.uniform_1i(
+            &self.shader.location(key, "NPoints")?,
+            self.points.len() as i32,
+        );
+        shader.uniform_1i(
+            &self.shader.location(key, "Hovered")?,
+            self.hovered.get().map_or(-1, |index| index as i32),
+        );
 
-        self.shader_program.use_program();
-        self.vertex_array.draw(TRIANGLE_FAN);
+        self.points_buffer.bind(SITES_BINDING);
+        self.fullscreen_pass.draw(&shader, target, viewport);
 
         Ok(())
     }